@@ -1,21 +1,19 @@
 use criterion::{black_box, criterion_group, criterion_main, Criterion};
-use pwd_rs::{
-    models::NewPassword,
-    ops::{encrypt_and_insert, establish_connection},
-};
+use pwd_rs::ops::{encrypt_and_insert, establish_connection, unlock_vault_key};
 pub fn criterion_benchmark(c: &mut Criterion) {
     let mut connection = establish_connection().unwrap();
+    let key = unlock_vault_key(&mut connection, "mymasterpassword").unwrap();
 
     c.bench_function("fib 20", |b| {
         b.iter(|| {
             encrypt_and_insert(
                 &mut connection,
-                black_box("mymasterpassword"),
+                black_box(&key),
                 black_box("test_info"),
-                black_box(Some("user123".to_string())),
-                black_box(Some("tester@test.com".to_string())),
-                black_box(Some("mycoolpassword".to_string())),
-                black_box(Some("some notes".to_string())),
+                black_box(Some("user123")),
+                black_box(Some("tester@test.com")),
+                black_box(Some("mycoolpassword")),
+                black_box(Some("some notes")),
             )
         })
     });
@@ -0,0 +1,5 @@
+-- Your SQL goes here
+
+-- records with version 0 still use one shared nonce for every field (see aes_nonce)
+-- records with version 1 store a nonce in front of each encrypted field
+ALTER TABLE password ADD COLUMN version INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1,7 @@
+-- Your SQL goes here
+
+-- vault-wide settings, such as the KDF header, stored as key/value pairs
+CREATE TABLE metadata(
+  key TEXT NOT NULL PRIMARY KEY,
+  value TEXT NOT NULL
+);
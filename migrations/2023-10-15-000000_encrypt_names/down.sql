@@ -0,0 +1,16 @@
+-- This file should undo anything in `up.sql`
+-- note that names which were already encrypted stay encrypted
+CREATE TABLE password_old(
+  id INTEGER NOT NULL PRIMARY KEY,
+  name TEXT NOT NULL UNIQUE,
+  username TEXT DEFAULT NULL,
+  email TEXT DEFAULT NULL,
+  pass TEXT DEFAULT NULL,
+  notes TEXT DEFAULT NULL,
+  aes_nonce TEXT NOT NULL,
+  version INTEGER NOT NULL DEFAULT 0
+);
+INSERT INTO password_old (id, name, username, email, pass, notes, aes_nonce, version)
+  SELECT id, name, username, email, pass, notes, aes_nonce, version FROM password;
+DROP TABLE password;
+ALTER TABLE password_old RENAME TO password;
@@ -0,0 +1,21 @@
+-- Your SQL goes here
+
+-- names are encrypted from record version 5 on, so they can't be UNIQUE anymore.
+-- lookups go through name_index instead, a keyed hash of the normalized name.
+-- sqlite can't drop a constraint, so the table is rebuilt. existing rows use their plaintext name
+-- as the index until they're re-encrypted the next time the vault is opened.
+CREATE TABLE password_new(
+  id INTEGER NOT NULL PRIMARY KEY,
+  name TEXT NOT NULL,
+  username TEXT DEFAULT NULL,
+  email TEXT DEFAULT NULL,
+  pass TEXT DEFAULT NULL,
+  notes TEXT DEFAULT NULL,
+  aes_nonce TEXT NOT NULL,
+  version INTEGER NOT NULL DEFAULT 0,
+  name_index TEXT NOT NULL UNIQUE
+);
+INSERT INTO password_new (id, name, username, email, pass, notes, aes_nonce, version, name_index)
+  SELECT id, name, username, email, pass, notes, aes_nonce, version, name FROM password;
+DROP TABLE password;
+ALTER TABLE password_new RENAME TO password;
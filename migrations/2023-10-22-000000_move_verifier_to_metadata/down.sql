@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+INSERT INTO password (name, username, email, pass, notes, aes_nonce, version, name_index)
+  SELECT '.master', NULL, NULL, value, NULL, '', 5, '.master' FROM metadata WHERE key = 'verifier';
+DELETE FROM metadata WHERE key = 'verifier';
@@ -0,0 +1,7 @@
+-- Your SQL goes here
+
+-- the master password verifier used to live in a special ".master" row of the password table,
+-- it's a vault setting like the KDF header, so it belongs in the metadata table
+INSERT OR REPLACE INTO metadata (key, value)
+  SELECT 'verifier', pass FROM password WHERE name_index = '.master' AND pass IS NOT NULL;
+DELETE FROM password WHERE name_index = '.master';
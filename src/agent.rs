@@ -0,0 +1,341 @@
+// a small agent, like ssh-agent or gpg-agent, that keeps an unlocked vault's data key in memory
+// so the master password only has to be typed once. it listens on a unix socket that only the same user can use,
+// and forgets the key (and exits) after it's been idle for a while, or when asked to with `pwd-rs lock`.
+//
+// the protocol is one line in, one line out, per connection:
+//   "key <vault path>" -> "ok <hex data key>", or "none" if the agent holds a different vault
+//   "lock"             -> "ok", and the agent exits
+// anything that goes wrong is answered with "err <message>".
+
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::secret::Key;
+use crate::vault::UnlockedVault;
+use crate::{Error, Result};
+
+/// Environment variable that overrides where the agent's socket is.
+pub const AGENT_SOCKET_ENV: &str = "PWD_RS_AGENT_SOCK";
+/// How long the agent waits for a request before locking itself, by default.
+pub const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(15 * 60);
+
+/// Where the agent's socket is: `PWD_RS_AGENT_SOCK` if it's set, otherwise `$XDG_RUNTIME_DIR/pwd-rs-agent.sock`,
+/// otherwise a directory only this user can access in the temp directory.
+pub fn socket_path() -> Result<PathBuf> {
+    if let Some(path) = std::env::var_os(AGENT_SOCKET_ENV) {
+        return Ok(PathBuf::from(path));
+    }
+    // the runtime dir is already private to this user
+    if let Some(dir) = std::env::var_os("XDG_RUNTIME_DIR") {
+        return Ok(PathBuf::from(dir).join("pwd-rs-agent.sock"));
+    }
+    private_temp_dir().map(|dir| dir.join("agent.sock"))
+}
+
+#[cfg(unix)]
+mod unix {
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::os::unix::fs::{DirBuilderExt, FileTypeExt, MetadataExt, PermissionsExt};
+    use std::os::unix::io::AsRawFd;
+    use std::os::unix::net::{UnixListener, UnixStream};
+    use std::path::{Path, PathBuf};
+    use std::time::{Duration, Instant};
+
+    use zeroize::Zeroizing;
+
+    use crate::secret::Key;
+    use crate::vault::UnlockedVault;
+    use crate::{Error, Result};
+
+    // requests are tiny, anything longer than this is garbage
+    const MAX_REQUEST_LENGTH: u64 = 4096;
+    // how long a client gets to send its request, so one stuck client can't hold the agent up
+    const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+    // how often the agent checks for new connections and the idle timeout
+    const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+    // agents are told apart by the vault they hold, so the same vault opened through different paths still matches
+    fn vault_id(vault_path: &str) -> String {
+        std::fs::canonicalize(vault_path)
+            .map(|path| path.to_string_lossy().into_owned())
+            .unwrap_or_else(|_| vault_path.to_string())
+    }
+
+    fn current_uid() -> u32 {
+        // SAFETY: geteuid can't fail and doesn't touch memory
+        unsafe { libc::geteuid() }
+    }
+
+    // the user id of the process on the other end of the socket
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    fn peer_uid(stream: &UnixStream) -> std::io::Result<u32> {
+        let mut credentials = libc::ucred {
+            pid: 0,
+            uid: 0,
+            gid: 0,
+        };
+        let mut length = std::mem::size_of::<libc::ucred>() as libc::socklen_t;
+        // SAFETY: the buffer and its length match what SO_PEERCRED writes
+        let result = unsafe {
+            libc::getsockopt(
+                stream.as_raw_fd(),
+                libc::SOL_SOCKET,
+                libc::SO_PEERCRED,
+                &mut credentials as *mut libc::ucred as *mut libc::c_void,
+                &mut length,
+            )
+        };
+        if result != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(credentials.uid)
+    }
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    fn peer_uid(stream: &UnixStream) -> std::io::Result<u32> {
+        let (mut uid, mut gid) = (0, 0);
+        // SAFETY: getpeereid only writes to the two ids
+        if unsafe { libc::getpeereid(stream.as_raw_fd(), &mut uid, &mut gid) } != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(uid)
+    }
+
+    // both ends check who they're talking to: the agent so it only hands keys to its own user,
+    // and clients so they don't use a key from someone else's agent
+    fn check_peer(stream: &UnixStream) -> Result<()> {
+        if peer_uid(stream)? != current_uid() {
+            return Err(Error::Config(
+                "the agent socket belongs to another user".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn private_temp_dir() -> Result<PathBuf> {
+        let dir = std::env::temp_dir().join(format!("pwd-rs-{}", current_uid()));
+        match std::fs::DirBuilder::new().mode(0o700).create(&dir) {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {}
+            Err(e) => return Err(e.into()),
+        }
+        // someone else could have created it first
+        let metadata = std::fs::symlink_metadata(&dir)?;
+        if !metadata.is_dir()
+            || metadata.uid() != current_uid()
+            || metadata.permissions().mode() & 0o077 != 0
+        {
+            return Err(Error::Config(format!(
+                "{} isn't a private directory, set PWD_RS_AGENT_SOCK to use another socket",
+                dir.display()
+            )));
+        }
+        Ok(dir)
+    }
+
+    enum Outcome {
+        Continue,
+        Lock,
+    }
+
+    pub fn serve(socket: &Path, vault: UnlockedVault, idle_timeout: Duration) -> Result<()> {
+        if let Ok(metadata) = std::fs::symlink_metadata(socket) {
+            if UnixStream::connect(socket).is_ok() {
+                return Err(Error::AlreadyExists(format!(
+                    "agent at {}",
+                    socket.display()
+                )));
+            }
+            // only a socket of ours can have been left behind by an agent that didn't exit cleanly,
+            // anything else at that path (like a file PWD_RS_AGENT_SOCK points at by mistake) is left alone
+            if !metadata.file_type().is_socket() || metadata.uid() != current_uid() {
+                return Err(Error::Config(format!(
+                    "{} already exists and isn't a socket left behind by an agent, set PWD_RS_AGENT_SOCK to use another path",
+                    socket.display()
+                )));
+            }
+            std::fs::remove_file(socket)?;
+        }
+        let listener = UnixListener::bind(socket)?;
+        std::fs::set_permissions(socket, std::fs::Permissions::from_mode(0o600))?;
+        listener.set_nonblocking(true)?;
+
+        let id = vault_id(vault.path());
+        let mut last_used = Instant::now();
+        let result = loop {
+            if last_used.elapsed() >= idle_timeout {
+                break Ok(());
+            }
+            match listener.accept() {
+                Ok((stream, _)) => {
+                    last_used = Instant::now();
+                    // a misbehaving client shouldn't take the agent down
+                    if let Ok(Outcome::Lock) = handle(stream, &id, vault.key()) {
+                        break Ok(());
+                    }
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                    std::thread::sleep(POLL_INTERVAL)
+                }
+                Err(e) => break Err(e.into()),
+            }
+        };
+        let _ = std::fs::remove_file(socket);
+        // dropping the vault wipes the key
+        drop(vault);
+        result
+    }
+
+    fn handle(mut stream: UnixStream, id: &str, key: &Key) -> Result<Outcome> {
+        stream.set_nonblocking(false)?;
+        stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+        if let Err(e) = check_peer(&stream) {
+            writeln!(stream, "err {}", e)?;
+            return Err(e);
+        }
+        let mut request = String::new();
+        BufReader::new((&stream).take(MAX_REQUEST_LENGTH)).read_line(&mut request)?;
+        let request = request.trim_end_matches(['\r', '\n']);
+        match request.split_once(' ') {
+            Some(("key", path)) if vault_id(path) == id => {
+                let response = Zeroizing::new(format!("ok {}\n", hex::encode(key.as_bytes())));
+                stream.write_all(response.as_bytes())?;
+            }
+            Some(("key", _)) => writeln!(stream, "none")?,
+            None if request == "lock" => {
+                writeln!(stream, "ok")?;
+                return Ok(Outcome::Lock);
+            }
+            _ => writeln!(stream, "err unknown request")?,
+        }
+        Ok(Outcome::Continue)
+    }
+
+    // sends a request, returning None if there's no agent listening
+    pub fn request(socket: &Path, request: &str) -> Result<Option<Zeroizing<String>>> {
+        let mut stream = match UnixStream::connect(socket) {
+            Ok(stream) => stream,
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    std::io::ErrorKind::NotFound | std::io::ErrorKind::ConnectionRefused
+                ) =>
+            {
+                return Ok(None)
+            }
+            Err(e) => return Err(e.into()),
+        };
+        check_peer(&stream)?;
+        stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+        writeln!(stream, "{}", request)?;
+        let mut response = Zeroizing::new(String::new());
+        BufReader::new((&stream).take(MAX_REQUEST_LENGTH)).read_line(&mut response)?;
+        let trimmed = response.trim_end();
+        if let Some(message) = trimmed.strip_prefix("err ") {
+            return Err(Error::Config(format!("agent: {}", message)));
+        }
+        Ok(Some(Zeroizing::new(trimmed.to_string())))
+    }
+}
+
+/// Serves `vault`'s data key on `socket` until `pwd-rs lock` is run or nothing has asked for it in `idle_timeout`.
+/// Only processes running as the same user can connect. The socket is removed and the key wiped when this returns.
+/// Fails with `Error::AlreadyExists` if another agent is already listening on `socket`.
+#[cfg(unix)]
+pub fn serve(socket: &Path, vault: UnlockedVault, idle_timeout: Duration) -> Result<()> {
+    unix::serve(socket, vault, idle_timeout)
+}
+#[cfg(not(unix))]
+pub fn serve(_socket: &Path, _vault: UnlockedVault, _idle_timeout: Duration) -> Result<()> {
+    Err(Error::Config(
+        "the agent is only supported on unix".to_string(),
+    ))
+}
+
+/// Asks the agent on `socket` for the data key of the vault at `vault_path`.
+/// Returns `None` if there's no agent running, or it holds a different vault.
+pub fn request_key(socket: &Path, vault_path: &str) -> Result<Option<Key>> {
+    let Some(response) = request(socket, &format!("key {}", vault_path))? else {
+        return Ok(None);
+    };
+    let Some(encoded) = response.strip_prefix("ok ") else {
+        return Ok(None);
+    };
+    let decoded = zeroize::Zeroizing::new(hex::decode(encoded)?);
+    let bytes: &[u8; 32] = decoded
+        .as_slice()
+        .try_into()
+        .map_err(|_| Error::Encoding("the agent sent a key with the wrong length".to_string()))?;
+    Ok(Some(Key::from_bytes(bytes)))
+}
+
+/// Tells the agent on `socket` to forget its key and exit. Returns false if there was no agent running.
+pub fn lock(socket: &Path) -> Result<bool> {
+    Ok(request(socket, "lock")?.is_some())
+}
+
+#[cfg(unix)]
+fn request(socket: &Path, line: &str) -> Result<Option<zeroize::Zeroizing<String>>> {
+    unix::request(socket, line)
+}
+#[cfg(not(unix))]
+fn request(_socket: &Path, _line: &str) -> Result<Option<zeroize::Zeroizing<String>>> {
+    Ok(None)
+}
+
+#[cfg(unix)]
+fn private_temp_dir() -> Result<PathBuf> {
+    unix::private_temp_dir()
+}
+#[cfg(not(unix))]
+fn private_temp_dir() -> Result<PathBuf> {
+    Ok(std::env::temp_dir())
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::time::Duration;
+
+    use crate::kdf::Kdf;
+    use crate::vault::Vault;
+
+    fn unlocked_vault() -> crate::vault::UnlockedVault {
+        let mut vault = Vault::open(":memory:").unwrap();
+        vault
+            .initialize("mymasterpassword", Kdf::DEFAULT_ARGON2ID)
+            .unwrap();
+        vault.unlock("mymasterpassword").unwrap()
+    }
+    fn test_socket(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("pwd-rs-agent-{}-{}.sock", name, std::process::id()))
+    }
+
+    #[test]
+    fn key_and_lock() {
+        let socket = test_socket("lock");
+        let vault = unlocked_vault();
+        let key = vault.key().clone();
+        let agent = {
+            let socket = socket.clone();
+            std::thread::spawn(move || super::serve(&socket, vault, Duration::from_secs(60)))
+        };
+        while !socket.exists() {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(super::request_key(&socket, ":memory:").unwrap(), Some(key));
+        // the agent only hands out the key of the vault it unlocked
+        assert_eq!(super::request_key(&socket, "other.db").unwrap(), None);
+
+        assert!(super::lock(&socket).unwrap());
+        agent.join().unwrap().unwrap();
+        assert!(!socket.exists());
+        assert!(!super::lock(&socket).unwrap());
+        assert_eq!(super::request_key(&socket, ":memory:").unwrap(), None);
+    }
+    #[test]
+    fn idle_timeout() {
+        let socket = test_socket("idle");
+        let vault = unlocked_vault();
+        super::serve(&socket, vault, Duration::from_millis(200)).unwrap();
+        assert!(!socket.exists());
+    }
+}
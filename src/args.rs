@@ -1,22 +1,112 @@
-use clap::{Parser, Subcommand};
+use std::path::PathBuf;
+
+use clap::error::ErrorKind;
+use clap::{CommandFactory, Parser, Subcommand, ValueEnum};
+
+use crate::agent::DEFAULT_IDLE_TIMEOUT;
+use crate::clipboard::DEFAULT_CLEAR_AFTER;
+use crate::console::Format;
+use crate::generator::{
+    Generated, GeneratorPolicy, PassphrasePolicy, PinPolicy, PronounceablePolicy,
+};
+use crate::input::{SecretSource, MASTER_PASSWORD_ENV};
+use crate::kdf::Kdf;
+use crate::vault::Field;
+use crate::Result;
 
 #[derive(Parser)]
 #[command(name = "pwd-rs")]
 #[command(author = "dvub <dvubdevs@gmail.com>")]
 #[command(version = "1.0.0")]
 #[command(about = "Client-side password management/generator CLI tool built with Rust.", long_about = None)]
-
+#[command(
+    after_help = "By default the master password is prompted for. It can also be set with the PWD_RS_MASTER environment variable.\nIf `pwd-rs agent` is running, its socket is found with PWD_RS_AGENT_SOCK or XDG_RUNTIME_DIR.\n\nExit codes:\n  0  success\n  1  not found\n  2  incorrect master password\n  3  already exists\n  4  database error\n  5  decryption failed\n  6  invalid input or arguments\n  7  i/o error\n  8  invalid stored data\n  9  configuration error"
+)]
 pub struct PwdArgs {
     /// Command to run
     #[command(subcommand)]
     pub command: PasswordCommands,
 
-    /// Master password
-    #[arg(short = 'P', long)]
-    pub master_password: String,
+    /// Path to the vault database, defaults to DATABASE_URL from the environment or .env
+    #[arg(long, global = true, value_name = "PATH")]
+    pub path: Option<String>,
+
+    /// Master password. It's visible in the process list and shell history,
+    /// so it's only accepted along with --insecure-password-arg
+    #[arg(short = 'P', long, global = true, group = "master_source")]
+    pub master_password: Option<String>,
+    /// Allow passing the master password with -P, or the new one with `master change -n`
+    #[arg(long, global = true)]
+    pub insecure_password_arg: bool,
+    /// Read the master password from the first line of stdin
+    #[arg(long, global = true, group = "master_source")]
+    pub password_stdin: bool,
+    /// Read the master password from the first line of an open file descriptor
+    #[arg(long, global = true, value_name = "FD", group = "master_source")]
+    pub password_fd: Option<i32>,
+    /// Read the master password from the first line of a file
+    #[arg(long, global = true, value_name = "PATH", group = "master_source")]
+    pub password_file: Option<PathBuf>,
+    /// Don't use a running agent, always ask for the master password
+    #[arg(long, global = true)]
+    pub no_agent: bool,
+    /// Only print results and errors, not the banner or progress messages
+    #[arg(short, long, global = true)]
+    pub quiet: bool,
+    /// Output format. With json, stdout only carries a single JSON document, including for errors
+    #[arg(long, global = true, value_enum, default_value_t = Format::Text)]
+    pub format: Format,
+}
+
+impl PwdArgs {
+    /// Checks that -P and `master change -n` come with --insecure-password-arg. clap's `requires` can't do this:
+    /// it misses the flag when it's given on a different level of the command line than the argument needing it.
+    pub fn check_insecure_password_args(&self) -> std::result::Result<(), clap::Error> {
+        let new_password = matches!(
+            &self.command,
+            PasswordCommands::Master {
+                command: MasterCommands::Change {
+                    new_password: Some(_),
+                    ..
+                }
+            }
+        );
+        if (self.master_password.is_some() || new_password) && !self.insecure_password_arg {
+            return Err(PwdArgs::command().error(
+                ErrorKind::MissingRequiredArgument,
+                "passwords on the command line are only accepted along with --insecure-password-arg",
+            ));
+        }
+        Ok(())
+    }
+    /// Where to read the master password from: one of the flags above if it was given,
+    /// otherwise PWD_RS_MASTER if it's set, otherwise a prompt.
+    pub fn master_password_source(&self) -> SecretSource {
+        if let Some(master_password) = &self.master_password {
+            SecretSource::Argument(master_password.clone())
+        } else if self.password_stdin {
+            SecretSource::Stdin
+        } else if let Some(fd) = self.password_fd {
+            SecretSource::Fd(fd)
+        } else if let Some(path) = &self.password_file {
+            SecretSource::File(path.clone())
+        } else if std::env::var_os(MASTER_PASSWORD_ENV).is_some() {
+            SecretSource::Env(MASTER_PASSWORD_ENV.to_string())
+        } else {
+            SecretSource::Prompt
+        }
+    }
 }
 #[derive(Subcommand)]
 pub enum PasswordCommands {
+    /// Create a new vault and set its master password
+    Init {
+        /// Key derivation function used to protect the vault
+        #[arg(long, value_enum, default_value_t = KdfAlgorithm::Argon2id)]
+        kdf: KdfAlgorithm,
+    },
+    /// Show the vault's status, including recent failed unlocks. Doesn't need the master password.
+    Status,
     /// Add a new password
     Add {
         /// Password name
@@ -41,6 +131,22 @@ pub enum PasswordCommands {
         /// The password name to search for
         #[arg(short = 'N', long)]
         name: String,
+        /// Copy a field to the clipboard instead of printing the password's data
+        #[arg(long)]
+        clip: bool,
+        /// Only print this field, without a label. Can be given more than once, each field is printed on its own line.
+        /// With --clip, the single field to copy (the password by default)
+        #[arg(short, long = "field", value_name = "FIELD", value_enum)]
+        fields: Vec<Field>,
+        /// Print ******** instead of secret fields (the password and notes), e.g. while sharing your screen
+        #[arg(long, conflicts_with = "clip")]
+        mask: bool,
+        /// Print secret fields even if --mask is given, e.g. by a shell alias
+        #[arg(long, conflicts_with = "clip")]
+        reveal: bool,
+        /// Clear the clipboard (or put back what was on it) after this many seconds
+        #[arg(long, value_name = "SECONDS", default_value_t = DEFAULT_CLEAR_AFTER.as_secs(), requires = "clip")]
+        clear_after: u64,
     },
     /// Prints a list of all passwords. This command will only print password names.
     List,
@@ -72,6 +178,81 @@ pub enum PasswordCommands {
         #[arg(short, long)]
         confirm: String,
     },
+    /// Unlock the vault and keep its key in memory, so other commands don't ask for the master password.
+    /// The agent runs in the foreground until `pwd-rs lock` is run or it's been idle for --timeout seconds.
+    Agent {
+        /// Lock the vault after this many seconds without a request
+        #[arg(short, long, default_value_t = DEFAULT_IDLE_TIMEOUT.as_secs())]
+        timeout: u64,
+    },
+    /// Tell a running agent to forget the vault key and exit
+    Lock,
+    /// Generate passwords without saving them. Doesn't need a vault or the master password
+    Generate {
+        /// How to generate the passwords, a strong random password by default
+        #[command(subcommand)]
+        generator_type: Option<GeneratorTypes>,
+        /// Number of passwords to generate
+        #[arg(short = 'n', long, global = true, default_value_t = 1)]
+        count: usize,
+        /// Print each password's entropy in bits next to it
+        #[arg(long, global = true)]
+        entropy: bool,
+    },
+    /// Manage the master password
+    Master {
+        #[command(subcommand)]
+        command: MasterCommands,
+    },
+    /// Manage the key derivation function used to encrypt the vault
+    Kdf {
+        #[command(subcommand)]
+        command: KdfCommands,
+    },
+}
+#[derive(Subcommand)]
+pub enum MasterCommands {
+    /// Change the master password. A backup of the database is written first,
+    /// and removed once the new password is in place.
+    /// The new password is prompted for unless it's passed with -n and -c.
+    Change {
+        /// New master password. It's visible in the process list and shell history,
+        /// so it's only accepted along with --insecure-password-arg
+        #[arg(short, long, requires = "confirm")]
+        new_password: Option<String>,
+        /// New master password, again
+        #[arg(short, long, requires = "new_password")]
+        confirm: Option<String>,
+    },
+}
+#[derive(Subcommand)]
+pub enum KdfCommands {
+    /// Benchmark this machine and pick KDF parameters for a target unlock time
+    Tune {
+        /// Key derivation function to tune
+        #[arg(short, long, value_enum, default_value_t = KdfAlgorithm::Argon2id)]
+        algorithm: KdfAlgorithm,
+        /// Target time for a single key derivation, in milliseconds
+        #[arg(short, long, default_value_t = 1000)]
+        target_ms: u64,
+        /// Re-wrap the vault key using the tuned parameters
+        #[arg(long)]
+        apply: bool,
+    },
+}
+#[derive(Clone, Copy, ValueEnum)]
+pub enum KdfAlgorithm {
+    Argon2id,
+    Pbkdf2,
+}
+impl KdfAlgorithm {
+    /// The default parameters for this algorithm
+    pub fn default_kdf(self) -> Kdf {
+        match self {
+            KdfAlgorithm::Argon2id => Kdf::DEFAULT_ARGON2ID,
+            KdfAlgorithm::Pbkdf2 => Kdf::DEFAULT_PBKDF2,
+        }
+    }
 }
 #[derive(Subcommand)]
 pub enum PasswordTypes {
@@ -80,11 +261,47 @@ pub enum PasswordTypes {
         /// Password
         #[arg(short, long)]
         password: String,
+        /// Refuse the password if its estimated strength is lower than this,
+        /// from 0 (guessable in minutes) to 4 (practically unguessable)
+        #[arg(long, value_name = "SCORE", value_parser = clap::value_parser!(u8).range(0..=4))]
+        min_score: Option<u8>,
     },
+    #[command(flatten)]
+    Generated(GeneratorTypes),
+}
+/// The ways to generate a password, for `add`, `update` and `generate`
+#[derive(Subcommand)]
+pub enum GeneratorTypes {
     /// Automatically generate a strong password (recommended)
     Auto {
-        /// Password length, max 32 characters
-        #[arg(short, long, default_value_t = 10)]
-        length: usize,
+        #[command(flatten)]
+        policy: GeneratorPolicy,
+    },
+    /// Generate a passphrase of random words, which is easier to type and remember
+    Passphrase {
+        #[command(flatten)]
+        policy: PassphrasePolicy,
+    },
+    /// Generate a numeric PIN, for phones and bank cards
+    Pin {
+        #[command(flatten)]
+        policy: PinPolicy,
     },
+    /// Generate a pronounceable password of alternating consonants and vowels, for reading out loud
+    Pronounceable {
+        #[command(flatten)]
+        policy: PronounceablePolicy,
+    },
+}
+
+impl GeneratorTypes {
+    /// Generates a password with this type's policy
+    pub fn generate(&self) -> Result<Generated> {
+        match self {
+            GeneratorTypes::Auto { policy } => policy.generate(),
+            GeneratorTypes::Passphrase { policy } => policy.generate(),
+            GeneratorTypes::Pin { policy } => policy.generate(),
+            GeneratorTypes::Pronounceable { policy } => policy.generate(),
+        }
+    }
 }
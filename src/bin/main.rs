@@ -1,107 +1,291 @@
 use clap::Parser;
 
-use colored::Colorize;
+use pwd_rs::agent;
 use pwd_rs::args::PwdArgs;
-use pwd_rs::console::{checking, error, success};
-use pwd_rs::ops::*;
-
-use pwd_rs::args::{PasswordCommands, PasswordTypes};
-use pwd_rs::console::print_pass;
-use pwd_rs::crypto::generate_password;
-
-fn main() {
-    let args = PwdArgs::parse();
-    // make it look pretty :)
-    // i took all the time to write this shit code so the final app better look nice
-    println!(
-        "{}",
-        "
-    ___            _            
-   | _ \\__ __ ____| |___ _ _ ___
-   |  _/\\ V  V / _` |___| '_(_-<
-   |_|   \\_/\\_/\\__,_|   |_| /__/"
-            .bold()
-    );
-    println!();
-    println!("{} {}!", "Welcome to".italic(), "pwd-rs".bold().green());
-    println!();
-    // a lot of rather busy work to do here,
-    // mostly checking master record, connecting to database, etc.
+use pwd_rs::clipboard::{self, SystemClipboard};
+use pwd_rs::console::{self, checking, error, info, success, warning};
+use pwd_rs::ops::database_url;
+use pwd_rs::vault::{Entry, EntryUpdate, Field, Vault};
 
-    // create connection
-    checking("connecting to local SQLite database");
-    let conn = establish_connection();
+use pwd_rs::args::{
+    GeneratorTypes, KdfAlgorithm, KdfCommands, MasterCommands, PasswordCommands, PasswordTypes,
+};
+use pwd_rs::console::{
+    entry_json, format_time, print_fields, print_generated, print_names, print_pass, print_status,
+};
+use pwd_rs::generator::GeneratorPolicy;
+use pwd_rs::input::{prompt_new_secret, read_secret, SecretSource};
+use pwd_rs::kdf::{tune, Kdf};
+use pwd_rs::secret::SecretString;
+use pwd_rs::strength;
+use pwd_rs::Error;
+use serde_json::{json, Value};
+use std::path::Path;
+use std::process::ExitCode;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-    let Ok(mut conn) = conn else {
-        error("could not connect application to local SQLite database");
-        return;
+fn main() -> ExitCode {
+    let args = match PwdArgs::try_parse().and_then(|args| {
+        args.check_insecure_password_args()?;
+        Ok(args)
+    }) {
+        Ok(args) => args,
+        // --help and --version end up here too, they aren't errors
+        Err(e) if !e.use_stderr() => {
+            let _ = e.print();
+            return ExitCode::SUCCESS;
+        }
+        // the arguments couldn't be parsed, so whether --format json was given has to be looked for by hand
+        Err(e) if json_requested() => {
+            let rendered = e.render().to_string();
+            let message = rendered.lines().next().unwrap_or_default();
+            let e = Error::InvalidInput(message.trim_start_matches("error: ").to_string());
+            print_error_json(&e);
+            return ExitCode::from(e.exit_code());
+        }
+        Err(e) => {
+            let _ = e.print();
+            return ExitCode::from(Error::InvalidInput(String::new()).exit_code());
+        }
     };
-    success("connected to local SQLite database");
-
-    // this is some logic to check create a new master record if one doesn't already exist
-    // the logic for this ended up being really complicated
-    checking("master record?");
-    let master_exists = check_password_exists(&mut conn, MASTER_KEYWORD);
-    match master_exists {
-        Ok(master) => {
-            if let PasswordCommands::Add { ref name, .. } = args.command {
-                if name == MASTER_KEYWORD {
-                    if master {
-                        error("master record already exists");
-                        return;
-                    }
-
-                    match insert_master_password(&mut conn, args.master_password.as_bytes()) {
-                        Ok(_) => {
-                            success("created new master record");
-                        }
-                        Err(_) => {
-                            error("error creating new master password");
-                        }
-                    }
-                    return;
-                }
+    console::set_format(args.format);
+    console::set_quiet(args.quiet);
+    console::banner();
+    match run(args) {
+        Ok(result) => {
+            if console::is_json() {
+                console::print_json(&result);
             }
+            ExitCode::SUCCESS
         }
         Err(e) => {
-            match e {
-                diesel::result::Error::NotFound => {
-                    error("master password not found");
-                }
-                _ => todo!(),
+            error(&e.to_string());
+            if console::is_json() {
+                print_error_json(&e);
             }
-            // println!("{}", e);
-            return;
+            ExitCode::from(e.exit_code())
         }
     }
-    success("found master record");
+}
+
+fn print_error_json(e: &Error) {
+    console::print_json(&json!({
+        "error": {
+            "code": e.exit_code(),
+            "kind": e.kind(),
+            "message": e.to_string(),
+        }
+    }));
+}
 
-    checking("authenticating with master record...");
-    match authenticate(&mut conn, args.master_password.as_bytes()) {
-        Ok(t) => {
-            if !t {
-                error("incorrect master password");
-                return;
+// whether the command line asks for json output, for when it can't be parsed
+fn json_requested() -> bool {
+    let args: Vec<String> = std::env::args().collect();
+    args.iter().any(|arg| arg == "--format=json")
+        || args
+            .windows(2)
+            .any(|pair| pair[0] == "--format" && pair[1] == "json")
+}
+
+// every command returns its result as json, which is only printed with `--format json`.
+// otherwise, everything is printed along the way instead.
+fn run(args: PwdArgs) -> pwd_rs::Result<Value> {
+    // a lot of rather busy work to do here,
+    // mostly connecting to the database, checking the master password, etc.
+
+    // locking only needs the agent, not the vault
+    if let PasswordCommands::Lock = args.command {
+        let locked = agent::lock(&agent::socket_path()?)?;
+        if locked {
+            success("locked the agent");
+        } else {
+            info("no agent was running");
+        }
+        return Ok(json!({ "locked": locked }));
+    }
+
+    // generating passwords doesn't need the vault at all
+    if let PasswordCommands::Generate {
+        generator_type,
+        count,
+        entropy,
+    } = &args.command
+    {
+        let default = GeneratorTypes::Auto {
+            policy: GeneratorPolicy::default(),
+        };
+        let generator_type = generator_type.as_ref().unwrap_or(&default);
+        let generated = (0..*count)
+            .map(|_| generator_type.generate())
+            .collect::<pwd_rs::Result<Vec<_>>>()?;
+        print_generated(&generated, *entropy);
+        return Ok(json!({
+            "passwords": generated
+                .iter()
+                .map(|generated| json!({
+                    "password": generated.password.expose_secret(),
+                    "entropy_bits": generated.entropy_bits,
+                }))
+                .collect::<Vec<_>>()
+        }));
+    }
+
+    // create connection, the database is created and migrated if needed
+    checking("connecting to local SQLite database");
+    let path = match &args.path {
+        Some(path) => path.clone(),
+        None => database_url()?,
+    };
+    let mut vault = Vault::open(&path)?;
+    success("connected to local SQLite database");
+
+    if let PasswordCommands::Init { kdf } = args.command {
+        if vault.is_initialized()? {
+            return Err(Error::AlreadyExists(format!("vault at {}", path)));
+        }
+        // a typo here would lock you out of the new vault for good, so prompts ask twice
+        let master_password = match args.master_password_source() {
+            SecretSource::Prompt => {
+                prompt_new_secret("new master password: ", "new master password, again: ")?
+            }
+            source => read_secret(&source, "")?,
+        };
+        checking("creating a new vault");
+        vault.initialize(master_password.expose_secret(), kdf.default_kdf())?;
+        success(&format!("created a new vault at {}", path));
+        return Ok(json!({ "created": path }));
+    }
+
+    let status = vault.status()?;
+    if let PasswordCommands::Status = args.command {
+        print_status(&path, &status);
+        return Ok(json!({
+            "path": path,
+            "initialized": status.initialized,
+            "kdf": status.kdf.map(|kdf| kdf.to_string()),
+            "failed_unlocks": status.failed_unlocks,
+            "last_failed_unlock": status
+                .last_failed_unlock
+                .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+                .map(|t| t.as_secs()),
+            "unlock_delay_secs": status.unlock_delay.as_secs_f64().ceil(),
+        }));
+    }
+
+    // benchmarking doesn't need the master password, only applying the result does
+    if let PasswordCommands::Kdf {
+        command:
+            KdfCommands::Tune {
+                algorithm,
+                target_ms,
+                apply: false,
+            },
+    } = args.command
+    {
+        let current = status.kdf.map(|kdf| kdf.to_string());
+        let tuned = tune_kdf(current.as_deref(), algorithm, target_ms)?;
+        info("run again with --apply to re-wrap the vault key with these parameters");
+        return Ok(json!({ "current": current, "tuned": tuned.to_string(), "applied": false }));
+    }
+
+    checking("vault is initialized?");
+    if !status.initialized {
+        info("run `pwd-rs init` to create a new vault");
+        return Err(Error::NotFound(format!("vault at {}", path)));
+    }
+    success("found vault");
+
+    // changing the vault's keys always needs the master password, and so does starting the agent
+    let needs_master_password = matches!(
+        args.command,
+        PasswordCommands::Master { .. }
+            | PasswordCommands::Kdf {
+                command: KdfCommands::Tune { apply: true, .. }
+            }
+            | PasswordCommands::Agent { .. }
+    );
+    let agent_key = if needs_master_password || args.no_agent {
+        None
+    } else {
+        // a broken runtime directory only means there can't be an agent, the master password still works
+        match agent::socket_path() {
+            Ok(socket) => agent::request_key(&socket, &path)?,
+            Err(e) => {
+                warning(&format!("not using the agent: {}", e));
+                None
             }
         }
-        Err(e) => {
-            match e {
-                diesel::result::Error::NotFound => {
-                    error("master password not found");
+    };
+    let (mut vault, master_password) = match agent_key {
+        Some(key) => {
+            let vault = vault.unlock_with_key(key).map_err(|e| {
+                if let Error::Authentication = e {
+                    info("the agent's key doesn't unlock this vault, run `pwd-rs lock` and try again");
                 }
-                _ => todo!(),
+                e
+            })?;
+            success("unlocked using the agent");
+            (vault, None)
+        }
+        None => {
+            let master_password = read_secret(&args.master_password_source(), "master password: ")?;
+            checking("authenticating with master password...");
+            if !status.unlock_delay.is_zero() {
+                info(&format!(
+                    "waiting {}s after {} failed unlocks",
+                    status.unlock_delay.as_secs_f64().ceil(),
+                    status.failed_unlocks
+                ));
+            }
+            // records written by older versions of pwd-rs get re-encrypted the first time the vault is unlocked
+            let vault = vault.unlock(master_password.expose_secret())?;
+            success("authenticated using master password");
+            // so someone guessing at the vault doesn't go unnoticed
+            if status.failed_unlocks > 0 {
+                warning(&format!(
+                    "{} failed unlock(s) since the last successful one, the last {}",
+                    status.failed_unlocks,
+                    status
+                        .last_failed_unlock
+                        .map(format_time)
+                        .unwrap_or_else(|| "at an unknown time".to_string())
+                ));
+            }
+            if vault.upgraded_records() > 0 {
+                success(&format!(
+                    "re-encrypted {} record(s) in the current format",
+                    vault.upgraded_records()
+                ));
+            }
+            for (old_name, new_name) in vault.renamed_records() {
+                warning(&format!(
+                    "\"{}\" was renamed to \"{}\", since names are no longer case sensitive",
+                    old_name, new_name
+                ));
             }
-            // println!("{}", e);
-            return;
+            (vault, Some(master_password))
         }
-    }
-
-    success("authenticated using master record");
-    println!();
+    };
+    info("");
     // a lot of checks and authentication is finall done,
     // now we have to get to actually doing the command the user wants
 
-    match args.command {
+    let result = match args.command {
+        // handled above, before unlocking
+        PasswordCommands::Init { .. }
+        | PasswordCommands::Status
+        | PasswordCommands::Lock
+        | PasswordCommands::Generate { .. } => Value::Null,
+        PasswordCommands::Agent { timeout } => {
+            let socket = agent::socket_path()?;
+            success(&format!(
+                "agent listening on {}, run `pwd-rs lock` to stop it",
+                socket.display()
+            ));
+            agent::serve(&socket, vault, Duration::from_secs(timeout))?;
+            success("agent locked");
+            json!({ "agent": "locked", "socket": socket })
+        }
         PasswordCommands::Add {
             name,
             email,
@@ -110,54 +294,89 @@ fn main() {
             password_type,
         } => {
             checking("password name is available?");
-            match check_password_exists(&mut conn, name.as_str()) {
-                Ok(master) => {
-                    if master {
-                        error("password with this name already exists \n\t modify or delete existing password instead");
-                        return;
-                    }
-                }
-                Err(_) => error("error checking if password exists"),
+            if vault.get(&name)?.is_some() {
+                return Err(Error::AlreadyExists(format!("password \"{}\"", name)));
             }
             success("password with this name is available");
 
-            let new_pass = match password_type {
-                Some(password_type) => match password_type {
-                    PasswordTypes::Manual { password } => Some(password),
-                    PasswordTypes::Auto { length } => Some(generate_password(length)),
-                },
-                None => None,
-            };
+            let user_inputs: Vec<&str> =
+                [Some(name.as_str()), username.as_deref(), email.as_deref()]
+                    .into_iter()
+                    .flatten()
+                    .collect();
+            let new_pass = password_type
+                .map(|password_type| new_password(password_type, &user_inputs))
+                .transpose()?;
 
-            match encrypt_and_insert(
-                &mut conn,
-                &args.master_password,
-                &name,
+            vault.add(Entry {
+                name: name.clone(),
                 username,
                 email,
-                new_pass,
+                pass: new_pass,
                 notes,
-            ) {
-                Ok(_) => {
-                    success("inserted new password into SQLite database");
+            })?;
+            success("inserted new password into SQLite database");
+            json!({ "added": name })
+        }
+        PasswordCommands::Get {
+            name,
+            clip,
+            fields,
+            mask,
+            reveal,
+            clear_after,
+        } => {
+            let found_password = vault
+                .get(&name)?
+                .ok_or(Error::NotFound(format!("password \"{}\"", name)))?;
+            success("found a password");
+            for &field in &fields {
+                if found_password.field(field).is_none() {
+                    return Err(Error::NotFound(format!(
+                        "{} of password \"{}\"",
+                        field, name
+                    )));
                 }
-                Err(_) => error("there was an error inserting the password"),
             }
-        }
-        PasswordCommands::Get { name } => {
-            let result = read_and_decrypt(&mut conn, &args.master_password, &name);
-            match result {
-                Ok(v) => match v {
-                    Some(found_password) => {
-                        success("found a password");
-                        println!();
-                        print_pass(found_password);
-                    }
-                    None => {
-                        error("no password was found with that name");
+            let masked = mask && !reveal;
+
+            if clip {
+                let field = match fields[..] {
+                    [] => Field::Pass,
+                    [field] => field,
+                    _ => {
+                        return Err(Error::InvalidInput(
+                            "--clip can only copy one field".to_string(),
+                        ))
                     }
-                },
-                Err(_) => error("error reading password"),
+                };
+                let value = found_password.field(field).ok_or(Error::NotFound(format!(
+                    "{} of password \"{}\"",
+                    field, name
+                )))?;
+                let mut clipboard = SystemClipboard::detect()?;
+                let copied = clipboard::copy(&mut clipboard, value)?;
+                // the vault isn't needed anymore, so the key doesn't have to stay in memory while waiting
+                drop(found_password);
+                drop(vault);
+                success(&format!(
+                    "copied {} to the clipboard, it will be cleared in {}s",
+                    field, clear_after
+                ));
+                let interrupted = clipboard::wait(Duration::from_secs(clear_after));
+                if interrupted {
+                    info("interrupted, clearing the clipboard now");
+                }
+                copied.restore(&mut clipboard)?;
+                success("cleared the clipboard");
+                json!({ "copied": field.to_string(), "cleared": true, "interrupted": interrupted })
+            } else if fields.is_empty() {
+                info("");
+                print_pass(&found_password, masked);
+                entry_json(&found_password, &fields, masked)
+            } else {
+                print_fields(&found_password, &fields, masked);
+                entry_json(&found_password, &fields, masked)
             }
         }
         PasswordCommands::Update {
@@ -168,56 +387,177 @@ fn main() {
             password_type,
             notes,
         } => {
-            let new_pass = match password_type {
-                Some(p) => match p {
-                    PasswordTypes::Auto { length } => Some(generate_password(length)),
-                    PasswordTypes::Manual { password } => Some(password),
-                },
-                None => None,
-            };
-            match encrypt_and_update(
-                &mut conn,
-                &args.master_password,
+            let existing = vault.get(&name)?;
+            let user_inputs: Vec<&str> = [
+                Some(name.as_str()),
+                new_name.as_deref(),
+                username.as_deref(),
+                email.as_deref(),
+                existing
+                    .as_ref()
+                    .and_then(|entry| entry.username.as_deref()),
+                existing.as_ref().and_then(|entry| entry.email.as_deref()),
+            ]
+            .into_iter()
+            .flatten()
+            .collect();
+            let new_pass = password_type
+                .map(|password_type| new_password(password_type, &user_inputs))
+                .transpose()?;
+            vault.update(
                 &name,
-                new_name,
-                username,
-                email,
-                new_pass,
-                notes,
-            ) {
-                Ok(_) => {
-                    success("updated password");
-                }
-                Err(_) => error("there was an issue updating the password"),
+                EntryUpdate {
+                    new_name: new_name.clone(),
+                    username,
+                    email,
+                    pass: new_pass,
+                    notes,
+                },
+            )?;
+            success("updated password");
+            json!({ "updated": name, "new_name": new_name })
+        }
+        PasswordCommands::List => {
+            let names = vault.list()?;
+            print_names(&names);
+            json!({ "names": names })
+        }
+        PasswordCommands::Delete { name, confirm } => {
+            if name != confirm {
+                return Err(Error::InvalidInput("name mismatch, aborting".to_string()));
             }
+            vault.delete(&name)?;
+            success("deleted password");
+            json!({ "deleted": name })
         }
-        PasswordCommands::List => match get_all(&mut conn) {
-            Ok(passwords) => {
-                println!(" --- all passwords (name only) --- ");
+        PasswordCommands::Master { command } => match command {
+            MasterCommands::Change {
+                new_password,
+                confirm,
+            } => {
+                let new_password = match (new_password, confirm) {
+                    (Some(new_password), Some(confirm)) => {
+                        let new_password = SecretString::new(new_password);
+                        if new_password != SecretString::new(confirm) {
+                            return Err(Error::InvalidInput(
+                                "password mismatch, aborting".to_string(),
+                            ));
+                        }
+                        new_password
+                    }
+                    _ => {
+                        prompt_new_secret("new master password: ", "new master password, again: ")?
+                    }
+                };
+                checking("backing up database");
+                let timestamp = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map(|t| t.as_secs())
+                    .unwrap_or_default();
+                // VACUUM INTO won't overwrite a file, so two changes within a second need different names
+                let mut backup = format!("{}.bak-{}", path, timestamp);
+                let mut n = 1;
+                while Path::new(&backup).exists() {
+                    backup = format!("{}.bak-{}-{}", path, timestamp, n);
+                    n += 1;
+                }
+                vault.backup(&backup)?;
+                success(&format!("backed up database to {}", backup));
 
-                for (i, p) in passwords.iter().enumerate() {
-                    println!("{}. {}", i + 1, p.name);
+                let master_password = required(&master_password)?;
+                if let Err(e) = vault.change_master_password(
+                    master_password.expose_secret(),
+                    new_password.expose_secret(),
+                ) {
+                    warning(&format!("the backup at {} was kept", backup));
+                    return Err(e);
                 }
-            }
-            Err(_) => {
-                error("there was an error retrieving all passwords");
+                success("changed master password");
+                // the backup still unlocks with the old master password, and holds the same data key
+                // as the live vault, so keeping it around would keep the old password working
+                if let Err(e) = std::fs::remove_file(&backup) {
+                    warning(&format!(
+                        "couldn't remove the backup at {}, delete it yourself: it still unlocks with the old master password and can decrypt the vault ({})",
+                        backup, e
+                    ));
+                }
+                json!({ "changed": "master password" })
             }
         },
-        PasswordCommands::Delete { name, confirm } => {
-            if name != confirm {
-                error("name mismatch, aborting");
-                return;
+        PasswordCommands::Kdf { command } => match command {
+            // without --apply, this was handled above, before unlocking
+            KdfCommands::Tune {
+                algorithm,
+                target_ms,
+                ..
+            } => {
+                let current = status.kdf.map(|kdf| kdf.to_string());
+                let tuned = tune_kdf(current.as_deref(), algorithm, target_ms)?;
+                vault.change_kdf(required(&master_password)?.expose_secret(), tuned)?;
+                success("re-wrapped the vault key with the tuned KDF");
+                json!({ "current": current, "tuned": tuned.to_string(), "applied": true })
             }
-            match delete_password(&mut conn, &name) {
-                Ok(_) => {
-                    success("deleted password");
-                }
-                Err(_) => {
-                    error("there was an error deleting this password");
+        },
+    };
+    Ok(result)
+}
+
+// benchmarks this machine for `algorithm` parameters that take `target_ms` to derive a key,
+// after showing the vault's `current` KDF
+fn tune_kdf(current: Option<&str>, algorithm: KdfAlgorithm, target_ms: u64) -> pwd_rs::Result<Kdf> {
+    info(&format!("current KDF: {}", current.unwrap_or("none")));
+    checking(&format!("benchmarking for a {}ms unlock", target_ms));
+    let tuned = tune(algorithm.default_kdf(), Duration::from_millis(target_ms))?;
+    success(&format!("tuned KDF: {}", tuned));
+    Ok(tuned)
+}
+
+// the password for `add` and `update`, typed in or generated.
+// typed in passwords are checked against `user_inputs` (the entry's name and so on) too, since they'd be tried first
+fn new_password(password_type: PasswordTypes, user_inputs: &[&str]) -> pwd_rs::Result<String> {
+    let generated = match password_type {
+        PasswordTypes::Manual {
+            password,
+            min_score,
+        } => {
+            let strength = strength::estimate(&password, user_inputs);
+            info(&format!(
+                "password strength: {}/4, estimated crack time: {}",
+                strength.score,
+                strength.crack_time()
+            ));
+            if let Some(min_score) = min_score {
+                if strength.score < min_score {
+                    return Err(Error::InvalidInput(format!(
+                        "the password's strength is {}/4 (it could be cracked in {}), but --min-score is {}",
+                        strength.score,
+                        strength.crack_time(),
+                        min_score
+                    )));
                 }
             }
+            if strength.score < strength::RECOMMENDED_SCORE {
+                warning(&format!(
+                    "weak password, {}",
+                    strength.warning.unwrap_or("it's easy to guess")
+                ));
+            }
+            return Ok(password);
         }
-    }
+        PasswordTypes::Generated(generator_type) => generator_type.generate()?,
+    };
+    success(&format!(
+        "generated a password with {:.1} bits of entropy",
+        generated.entropy_bits
+    ));
+    Ok(generated.password.into_inner())
+}
+
+// commands that need the master password never use the agent, so it's always been read for them
+fn required(master_password: &Option<SecretString>) -> pwd_rs::Result<&SecretString> {
+    master_password.as_ref().ok_or(Error::Config(
+        "this command needs the master password".to_string(),
+    ))
 }
 
 #[test]
@@ -225,3 +565,34 @@ fn verify_cli() {
     use clap::CommandFactory;
     PwdArgs::command().debug_assert()
 }
+
+#[test]
+fn insecure_password_args() {
+    let check = |args: &[&str]| {
+        PwdArgs::try_parse_from(args)
+            .unwrap()
+            .check_insecure_password_args()
+            .is_ok()
+    };
+    assert!(!check(&["pwd-rs", "-P", "m", "list"]));
+    assert!(check(&[
+        "pwd-rs",
+        "-P",
+        "m",
+        "list",
+        "--insecure-password-arg"
+    ]));
+    assert!(!check(&[
+        "pwd-rs", "master", "change", "-n", "a", "-c", "a"
+    ]));
+    assert!(check(&[
+        "pwd-rs",
+        "--insecure-password-arg",
+        "master",
+        "change",
+        "-n",
+        "a",
+        "-c",
+        "a"
+    ]));
+}
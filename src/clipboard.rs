@@ -0,0 +1,286 @@
+// copying secrets to the clipboard instead of printing them, where they'd stay in the terminal's scrollback.
+// whatever was copied is cleared again after a timeout (or the old contents are put back).
+
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use base64::Engine;
+use zeroize::Zeroize;
+
+use crate::crypto::constant_time_eq;
+use crate::secret::SecretString;
+use crate::{Error, Result};
+
+/// How long a copied secret stays on the clipboard, by default.
+pub const DEFAULT_CLEAR_AFTER: Duration = Duration::from_secs(45);
+
+// set by the signal handler wait() installs, so Ctrl-C ends the wait instead of the whole process
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// Somewhere secrets can be copied to.
+pub trait Clipboard {
+    /// Returns what's on the clipboard now, or `None` if it's empty or can't be read (e.g. with OSC 52).
+    fn get(&mut self) -> Result<Option<SecretString>>;
+    /// Replaces what's on the clipboard.
+    fn set(&mut self, contents: &str) -> Result<()>;
+    /// Empties the clipboard.
+    fn clear(&mut self) -> Result<()>;
+}
+
+/// A clipboard that only exists in memory, for tests.
+#[derive(Debug, Default)]
+pub struct MemoryClipboard {
+    pub contents: Option<SecretString>,
+}
+
+impl Clipboard for MemoryClipboard {
+    fn get(&mut self) -> Result<Option<SecretString>> {
+        Ok(self.contents.clone())
+    }
+    fn set(&mut self, contents: &str) -> Result<()> {
+        self.contents = Some(SecretString::from(contents));
+        Ok(())
+    }
+    fn clear(&mut self) -> Result<()> {
+        self.contents = None;
+        Ok(())
+    }
+}
+
+/// The system clipboard, through whichever of these tools is installed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SystemClipboard {
+    /// `wl-copy` and `wl-paste`, on Wayland
+    Wayland,
+    /// `xclip`, on X11
+    Xclip,
+    /// `xsel`, on X11
+    Xsel,
+    /// OSC 52 escape sequences, which most terminals turn into a clipboard write, even over ssh.
+    /// The clipboard can't be read back this way, so it's always cleared rather than restored.
+    Osc52,
+}
+
+impl SystemClipboard {
+    /// Picks a clipboard for the current session: Wayland or X11 if there's a display and the tool for it is installed,
+    /// otherwise OSC 52 if there's a terminal. Fails with `Error::Config` if there's nothing to copy to.
+    pub fn detect() -> Result<SystemClipboard> {
+        if std::env::var_os("WAYLAND_DISPLAY").is_some() && is_installed("wl-copy") {
+            return Ok(SystemClipboard::Wayland);
+        }
+        if std::env::var_os("DISPLAY").is_some() {
+            if is_installed("xclip") {
+                return Ok(SystemClipboard::Xclip);
+            }
+            if is_installed("xsel") {
+                return Ok(SystemClipboard::Xsel);
+            }
+        }
+        if std::env::var_os("TERM").is_some_and(|term| term != "dumb") {
+            return Ok(SystemClipboard::Osc52);
+        }
+        Err(Error::Config(
+            "no clipboard found, install wl-clipboard, xclip or xsel, or use a terminal that supports OSC 52"
+                .to_string(),
+        ))
+    }
+
+    // the commands that write (from stdin) and read (to stdout) the clipboard
+    fn commands(self) -> Option<(&'static [&'static str], &'static [&'static str])> {
+        match self {
+            SystemClipboard::Wayland => Some((&["wl-copy"], &["wl-paste", "--no-newline"])),
+            SystemClipboard::Xclip => Some((
+                &["xclip", "-selection", "clipboard", "-in"],
+                &["xclip", "-selection", "clipboard", "-out"],
+            )),
+            SystemClipboard::Xsel => Some((
+                &["xsel", "--clipboard", "--input"],
+                &["xsel", "--clipboard", "--output"],
+            )),
+            SystemClipboard::Osc52 => None,
+        }
+    }
+}
+
+impl Clipboard for SystemClipboard {
+    fn get(&mut self) -> Result<Option<SecretString>> {
+        let Some((_, read)) = self.commands() else {
+            return Ok(None);
+        };
+        let output = Command::new(read[0])
+            .args(&read[1..])
+            .stdin(Stdio::null())
+            .stderr(Stdio::null())
+            .output()?;
+        // an empty clipboard is reported as a failure by some of these
+        if !output.status.success() || output.stdout.is_empty() {
+            return Ok(None);
+        }
+        // anything that isn't text (like an image) can't be put back, so it's treated like a clipboard that can't be read
+        match String::from_utf8(output.stdout) {
+            Ok(contents) => Ok(Some(SecretString::new(contents))),
+            Err(e) => {
+                e.into_bytes().zeroize();
+                Ok(None)
+            }
+        }
+    }
+    fn set(&mut self, contents: &str) -> Result<()> {
+        let Some((write, _)) = self.commands() else {
+            return write_osc52(contents);
+        };
+        // these tools fork to keep serving the clipboard, and the fork holds on to stdout,
+        // so it has to be discarded rather than captured or waiting would never end
+        let mut child = Command::new(write[0])
+            .args(&write[1..])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()?;
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin.write_all(contents.as_bytes())?;
+        }
+        if !child.wait()?.success() {
+            return Err(Error::Config(format!("{} failed", write[0])));
+        }
+        Ok(())
+    }
+    fn clear(&mut self) -> Result<()> {
+        match self {
+            SystemClipboard::Wayland => {
+                Command::new("wl-copy")
+                    .arg("--clear")
+                    .stdout(Stdio::null())
+                    .stderr(Stdio::null())
+                    .status()?;
+                Ok(())
+            }
+            _ => self.set(""),
+        }
+    }
+}
+
+fn is_installed(program: &str) -> bool {
+    std::env::var_os("PATH")
+        .is_some_and(|paths| std::env::split_paths(&paths).any(|dir| dir.join(program).is_file()))
+}
+
+// the sequence goes to the controlling terminal, so it works even if stdout is redirected
+fn write_osc52(contents: &str) -> Result<()> {
+    let encoded =
+        zeroize::Zeroizing::new(base64::engine::general_purpose::STANDARD.encode(contents));
+    let sequence = zeroize::Zeroizing::new(format!("\x1b]52;c;{}\x07", encoded.as_str()));
+    match std::fs::OpenOptions::new().write(true).open("/dev/tty") {
+        Ok(mut tty) => tty.write_all(sequence.as_bytes())?,
+        Err(_) => std::io::stderr().write_all(sequence.as_bytes())?,
+    }
+    Ok(())
+}
+
+/// A secret that's been copied by `copy()`, along with what was on the clipboard before.
+#[derive(Debug)]
+pub struct CopiedSecret {
+    secret: SecretString,
+    previous: Option<SecretString>,
+}
+
+/// Copies `secret` to `clipboard`, remembering what was there before.
+/// Call `CopiedSecret::restore()` once it's been on the clipboard for long enough.
+pub fn copy(clipboard: &mut dyn Clipboard, secret: &str) -> Result<CopiedSecret> {
+    let previous = clipboard.get()?;
+    clipboard.set(secret)?;
+    Ok(CopiedSecret {
+        secret: SecretString::from(secret),
+        previous,
+    })
+}
+
+impl CopiedSecret {
+    /// Puts back whatever was on the clipboard before the secret was copied, or clears it
+    /// if that can't be read. If something else has been copied since, it's left alone.
+    pub fn restore(self, clipboard: &mut dyn Clipboard) -> Result<()> {
+        let secret = self.secret.expose_secret();
+        // a clipboard that can't be read is always cleared
+        let still_ours = match clipboard.get()? {
+            Some(current) => constant_time_eq(current.expose_secret(), secret),
+            None => true,
+        };
+        if !still_ours {
+            return Ok(());
+        }
+        match &self.previous {
+            Some(previous) if !constant_time_eq(previous.expose_secret(), secret) => {
+                clipboard.set(previous.expose_secret())
+            }
+            _ => clipboard.clear(),
+        }
+    }
+}
+
+/// Waits for `duration` before a copied secret is cleared. SIGINT (Ctrl-C), SIGTERM and SIGHUP end the wait early
+/// instead of ending the process, so the clipboard still gets cleared. Returns true if the wait was cut short.
+pub fn wait(duration: Duration) -> bool {
+    #[cfg(unix)]
+    for signal in [libc::SIGINT, libc::SIGTERM, libc::SIGHUP] {
+        // SAFETY: the handler only stores to an atomic, which is async-signal-safe
+        unsafe {
+            libc::signal(
+                signal,
+                on_signal as extern "C" fn(libc::c_int) as libc::sighandler_t,
+            )
+        };
+    }
+    let deadline = Instant::now() + duration;
+    while !INTERRUPTED.load(Ordering::SeqCst) {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return false;
+        }
+        // short naps, so a signal is noticed quickly
+        std::thread::sleep(remaining.min(Duration::from_millis(100)));
+    }
+    true
+}
+
+#[cfg(unix)]
+extern "C" fn on_signal(_signal: libc::c_int) {
+    INTERRUPTED.store(true, Ordering::SeqCst);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{copy, Clipboard, MemoryClipboard};
+
+    fn contents(clipboard: &MemoryClipboard) -> Option<&str> {
+        clipboard.contents.as_ref().map(|c| c.expose_secret())
+    }
+
+    #[test]
+    fn restores_previous_contents() {
+        let mut clipboard = MemoryClipboard::default();
+        clipboard.set("something else").unwrap();
+        let copied = copy(&mut clipboard, "hunter2").unwrap();
+        assert_eq!(contents(&clipboard), Some("hunter2"));
+        copied.restore(&mut clipboard).unwrap();
+        assert_eq!(contents(&clipboard), Some("something else"));
+    }
+    #[test]
+    fn clears_empty_clipboard() {
+        let mut clipboard = MemoryClipboard::default();
+        let copied = copy(&mut clipboard, "hunter2").unwrap();
+        assert_eq!(contents(&clipboard), Some("hunter2"));
+        copied.restore(&mut clipboard).unwrap();
+        assert_eq!(contents(&clipboard), None);
+    }
+    #[test]
+    fn leaves_newer_contents_alone() {
+        let mut clipboard = MemoryClipboard::default();
+        let copied = copy(&mut clipboard, "hunter2").unwrap();
+        // the user copies something else before the timeout
+        clipboard.set("something else").unwrap();
+        copied.restore(&mut clipboard).unwrap();
+        assert_eq!(contents(&clipboard), Some("something else"));
+    }
+}
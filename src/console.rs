@@ -1,42 +1,267 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
 use colored::Colorize;
 
-use crate::models::Password;
+use crate::generator::Generated;
+use crate::vault::{Entry, Field, Status};
+
+// diagnostics (the banner, progress and errors) go to stderr, so stdout only carries results.
+// in json mode, stdout only carries the json document: results print nothing, and only warnings still go to
+// stderr (without colour, for whatever reads it)
+static JSON_OUTPUT: AtomicBool = AtomicBool::new(false);
+// quiet mode hides the banner and progress, but not warnings, errors or results
+static QUIET: AtomicBool = AtomicBool::new(false);
+
+/// What's printed instead of secret fields with `get --mask`.
+pub const MASK: &str = "********";
+
+/// How results are printed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum Format {
+    /// Colourful, human readable text
+    #[default]
+    Text,
+    /// A single JSON document on stdout, for scripts
+    Json,
+}
+
+pub fn set_format(format: Format) {
+    JSON_OUTPUT.store(format == Format::Json, Ordering::Relaxed);
+}
+pub fn is_json() -> bool {
+    JSON_OUTPUT.load(Ordering::Relaxed)
+}
+pub fn set_quiet(quiet: bool) {
+    QUIET.store(quiet, Ordering::Relaxed);
+}
+pub fn is_quiet() -> bool {
+    QUIET.load(Ordering::Relaxed)
+}
+// whether the banner and progress messages are shown
+fn is_verbose() -> bool {
+    !is_json() && !is_quiet()
+}
+
+pub fn banner() {
+    if !is_verbose() {
+        return;
+    }
+    // make it look pretty :)
+    // i took all the time to write this shit code so the final app better look nice
+    eprintln!(
+        "{}",
+        "
+    ___            _            
+   | _ \\__ __ ____| |___ _ _ ___
+   |  _/\\ V  V / _` |___| '_(_-<
+   |_|   \\_/\\_/\\__,_|   |_| /__/"
+            .bold()
+    );
+    eprintln!();
+    eprintln!("{} {}!", "Welcome to".italic(), "pwd-rs".bold().green());
+    eprintln!();
+}
 pub fn checking(message: &str) {
-    println!("{}: {}", "checking".yellow().bold(), message);
+    if !is_verbose() {
+        return;
+    }
+    eprintln!("{}: {}", "checking".yellow().bold(), message);
 }
 pub fn success(message: &str) {
-    println!("{}: {}", "success".green().bold(), message);
+    if !is_verbose() {
+        return;
+    }
+    eprintln!("{}: {}", "success".green().bold(), message);
+}
+pub fn warning(message: &str) {
+    if is_json() {
+        eprintln!("warning: {}", message);
+        return;
+    }
+    eprintln!("{}: {}", "warning".yellow().bold(), message);
 }
+// a plain line of text, for anything that isn't a status message
+pub fn info(message: &str) {
+    if !is_verbose() {
+        return;
+    }
+    eprintln!("{}", message);
+}
+// errors are printed by main in json mode, as part of the document
 pub fn error(message: &str) {
-    println!("{}: {}", "error".red().bold(), message);
+    if is_json() {
+        return;
+    }
+    eprintln!("{}: {}", "error".red().bold(), message);
 }
-pub fn print_pass(password: Password) {
+pub fn print_json(value: &serde_json::Value) {
+    // serializing a Value can't fail
+    println!(
+        "{}",
+        serde_json::to_string_pretty(value).unwrap_or_default()
+    );
+}
+pub fn print_names(names: &[String]) {
+    if is_json() {
+        return;
+    }
+    println!(" --- all passwords (name only) --- ");
+    for (i, n) in names.iter().enumerate() {
+        println!("{}. {}", i + 1, n);
+    }
+}
+pub fn print_status(path: &str, status: &Status) {
+    if is_json() {
+        return;
+    }
+    println!(" --- {}: {} --- ", "vault".bold(), path);
+    if !status.initialized {
+        println!("not initialized, run `pwd-rs init` to create a new vault");
+        return;
+    }
+    match status.kdf {
+        Some(kdf) => println!("{}: {}", "KDF".bold(), kdf),
+        None => println!("{}: none", "KDF".bold()),
+    }
+    println!(
+        "{}: {}",
+        "failed unlocks since the last successful one".bold(),
+        status.failed_unlocks
+    );
+    match status.last_failed_unlock {
+        Some(time) => println!("{}: {}", "last failed unlock".bold(), format_time(time)),
+        None => println!("{}: never", "last failed unlock".bold()),
+    }
+    if !status.unlock_delay.is_zero() {
+        println!(
+            "{}: {}s",
+            "next unlock is delayed by".bold(),
+            status.unlock_delay.as_secs_f64().ceil()
+        );
+    }
+}
+// e.g. "1697000000 (5 minutes ago)", there's no date formatting without pulling in chrono
+pub fn format_time(time: SystemTime) -> String {
+    let timestamp = time
+        .duration_since(UNIX_EPOCH)
+        .map(|t| t.as_secs())
+        .unwrap_or_default();
+    let ago = SystemTime::now()
+        .duration_since(time)
+        .unwrap_or_default()
+        .as_secs();
+    let (amount, unit) = match ago {
+        0..=59 => (ago, "second"),
+        60..=3599 => (ago / 60, "minute"),
+        3600..=86399 => (ago / 3600, "hour"),
+        _ => (ago / 86400, "day"),
+    };
+    let plural = if amount == 1 { "" } else { "s" };
+    format!("{} ({} {}{} ago)", timestamp, amount, unit, plural)
+}
+// the value of a field as it's shown, which is the mask for secret fields if `masked` is set
+fn displayed(field: Field, value: &str, masked: bool) -> &str {
+    if masked && field.is_secret() {
+        MASK
+    } else {
+        value
+    }
+}
+pub fn print_pass(password: &Entry, masked: bool) {
+    if is_json() {
+        return;
+    }
     println!(" --- {}: {} --- ", "name".bold(), password.name);
-    let data = vec![
-        password.email,
-        password.username,
-        password.pass,
-        password.notes,
+    let data = [
+        (Field::Email, &password.email),
+        (Field::Username, &password.username),
+        (Field::Pass, &password.pass),
+        (Field::Notes, &password.notes),
     ];
     // FP (ftw) to check if the array of password fields contains only `none` and print a message
-    if data.iter().all(|field| field.is_none()) {
+    if data.iter().all(|(_, value)| value.is_none()) {
         println!();
         println!("no other data found for this record");
     }
 
-    for (index, field) in data.iter().enumerate() {
-        match field {
-            Some(m) => {
-                let name = match index {
-                    0 => "email".bold().bright_red(),
-                    1 => "username".bold(),
-                    2 => "password".bold().red(),
-                    3 => "notes".bold(),
-                    _ => "".bold(),
-                };
-                println!("{}: {}", name, m);
-            }
-            None => {}
+    for (field, value) in data {
+        if let Some(m) = value {
+            let name = match field {
+                Field::Email => "email".bold().bright_red(),
+                Field::Pass => "password".bold().red(),
+                field => field.to_string().bold(),
+            };
+            println!("{}: {}", name, displayed(field, m, masked));
         }
     }
 }
+// just the values, one per line, so they can be used in scripts.
+// every field has to be set, otherwise the lines wouldn't line up with the fields
+pub fn print_fields(password: &Entry, fields: &[Field], masked: bool) {
+    if is_json() {
+        return;
+    }
+    for &field in fields {
+        println!(
+            "{}",
+            displayed(field, password.field(field).unwrap_or_default(), masked)
+        );
+    }
+}
+// one password per line, so they can be used in scripts
+pub fn print_generated(passwords: &[Generated], entropy: bool) {
+    if is_json() {
+        return;
+    }
+    for generated in passwords {
+        if entropy {
+            println!(
+                "{}\t{:.1} bits",
+                generated.password.expose_secret(),
+                generated.entropy_bits
+            );
+        } else {
+            println!("{}", generated.password.expose_secret());
+        }
+    }
+}
+/// The JSON for `fields` of `password` (or all of them if `fields` is empty), masked like it's printed.
+pub fn entry_json(password: &Entry, fields: &[Field], masked: bool) -> serde_json::Value {
+    let fields = if fields.is_empty() {
+        <Field as clap::ValueEnum>::value_variants()
+    } else {
+        fields
+    };
+    let object = fields
+        .iter()
+        .map(|&field| {
+            let value = password
+                .field(field)
+                .map(|value| displayed(field, value, masked));
+            (field.to_string(), serde_json::json!(value))
+        })
+        .collect();
+    serde_json::Value::Object(object)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{entry_json, MASK};
+    use crate::vault::{Entry, Field};
+
+    #[test]
+    fn masked_json() {
+        let mut entry = Entry::default();
+        entry.name = "github".to_string();
+        entry.pass = Some("hunter2".to_string());
+        entry.notes = Some("recovery codes".to_string());
+        let all = entry_json(&entry, &[], true);
+        assert_eq!(all["name"], "github");
+        assert_eq!(all["pass"], MASK);
+        assert_eq!(all["notes"], MASK);
+        assert!(all["email"].is_null());
+        let selected = entry_json(&entry, &[Field::Pass], false);
+        assert_eq!(selected, serde_json::json!({ "pass": "hunter2" }));
+    }
+}
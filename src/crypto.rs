@@ -1,47 +1,72 @@
 // blehhhh
 
 use aes_gcm::{
-    aead::{
-        consts::{B0, B1},
-        generic_array::GenericArray,
-    },
-    aead::{Aead, KeyInit},
-    Aes256Gcm, Key,
+    aead::generic_array::GenericArray,
+    aead::{Aead, KeyInit, Payload},
+    AeadCore, Aes256Gcm, Key as AesKey,
 };
-use pbkdf2::pbkdf2_hmac;
-use rand::{rngs::OsRng, Rng};
-use sha2::{
-    digest::typenum::{UInt, UTerm},
-    Digest, Sha256,
+use argon2::{
+    password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Argon2,
 };
+use hmac::{Hmac, Mac};
+use rand::{rngs::OsRng, Rng};
+use sha2::{digest::Output, Digest, Sha256};
+use subtle::ConstantTimeEq;
+use zeroize::{Zeroize, Zeroizing};
+
+use crate::kdf::Kdf;
+use crate::secret::{Key, SecretString};
+use crate::{Error, Result};
+
+// length in bytes of an AES-GCM nonce, which is prepended to every ciphertext
+const NONCE_LENGTH: usize = 12;
 
 /// Hashes `text` using `Sha256`.
-pub fn hash(
-    text: &[u8],
-) -> GenericArray<u8, UInt<UInt<UInt<UInt<UInt<UInt<UTerm, B1>, B0>, B0>, B0>, B0>, B0>> {
+pub fn hash(text: &[u8]) -> Output<Sha256> {
     let mut hasher = Sha256::new();
     hasher.update(text);
     hasher.finalize()
 }
-// pbkdf2 function
-fn derive_key(master_password: impl AsRef<[u8]>, kdf_salt: impl AsRef<[u8]>) -> [u8; 32] {
-    // number of iterations
-    // correct me if i'm wrong on the following::
-
-    // this is one of the more important pieces of data that a bad actor would need
-    // if someone happened to know a user's master password, the next thing to know would be the salt (which is stored in the database)
-    // and then the number of iterations, to replicate keys
-
-    // if this was closed source and the code was obfuscated, this wouldn't be exposed information
-    let n = 4096;
-    let mut derived_key = [0u8; 32];
-    pbkdf2_hmac::<Sha256>(
-        master_password.as_ref(),
-        kdf_salt.as_ref(),
-        n,
-        &mut derived_key,
-    );
-    derived_key
+/// Builds a verifier for the master password using Argon2id and a random salt.
+/// The result is a PHC string (`$argon2id$v=19$m=...,t=...,p=...$salt$hash`),
+/// so the salt and KDF parameters are saved in the vault next to the hash.
+/// Vaults with a data key don't store one, their master password is checked by unwrapping the key instead.
+pub fn hash_master_password(master_password: impl AsRef<[u8]>) -> Result<String> {
+    let salt = SaltString::generate(&mut OsRng);
+    Ok(Argon2::default()
+        .hash_password(master_password.as_ref(), &salt)
+        .map_err(|e| Error::Config(format!("error hashing master password: {}", e)))?
+        .to_string())
+}
+/// Checks `master_password` against a verifier from `hash_master_password()`.
+/// The parameters stored in the verifier are used, not the current defaults.
+/// Fails with `Error::Encoding` if the verifier isn't a valid PHC string.
+pub fn verify_master_password(master_password: impl AsRef<[u8]>, verifier: &str) -> Result<bool> {
+    let parsed = PasswordHash::new(verifier)
+        .map_err(|e| Error::Encoding(format!("malformed master password verifier: {}", e)))?;
+    Ok(Argon2::default()
+        .verify_password(master_password.as_ref(), &parsed)
+        .is_ok())
+}
+/// Compares two secrets in constant time, so how long the comparison takes doesn't depend on
+/// where (or whether) they differ. Every comparison of secret material should go through this, never `==`.
+/// Only the lengths are allowed to leak: slices of different lengths return false straight away.
+pub fn constant_time_eq(a: impl AsRef<[u8]>, b: impl AsRef<[u8]>) -> bool {
+    a.as_ref().ct_eq(b.as_ref()).into()
+}
+/// Returns true if `verifier` is an unsalted SHA-256 hash written by older versions of pwd-rs.
+pub fn is_legacy_verifier(verifier: &str) -> bool {
+    !verifier.starts_with('$')
+}
+/// Derives the per-record key used before vaults had a KDF header:
+/// 4096 iterations of PBKDF2-HMAC-SHA256 with the record's name as the salt.
+/// This is only needed to read records that haven't been re-encrypted yet.
+pub fn derive_legacy_key(
+    master_password: impl AsRef<[u8]>,
+    kdf_salt: impl AsRef<[u8]>,
+) -> Result<Key> {
+    Kdf::Pbkdf2 { iterations: 4096 }.derive_key(master_password, kdf_salt.as_ref())
 }
 // i know this code smells pretty bad, i'm sorry
 // this is just really the easiest way i could think of
@@ -50,72 +75,173 @@ fn derive_key(master_password: impl AsRef<[u8]>, kdf_salt: impl AsRef<[u8]>) ->
 // if i was more experienced with rust i would refactor everything, but i'm not.
 // so you get this for now.
 // i'll point out the main problems with this
+
+/// Builds the associated data that binds an encrypted field to its record, column and format version.
+/// It's authenticated by AES-GCM but not stored, so a ciphertext moved to another row or column
+/// (or a row whose version was changed) will fail to decrypt.
+pub fn associated_data(record_id: i32, field: &str, version: i32) -> Vec<u8> {
+    format!("pwd-rs:{}:{}:{}", version, record_id, field).into_bytes()
+}
+/// Encrypts `data` under `key` with a fresh random nonce, authenticating `associated_data` along with it.
+/// The result is hex encoded `nonce || ciphertext`, so every field carries its own nonce
+/// and a nonce is never reused under the same key.
 pub fn encrypt(
-    master_password: impl AsRef<[u8]>,
+    key: &Key,
     data: Option<impl AsRef<[u8]>>, // this function should not even take in an optional parameter
-    aes_nonce: impl AsRef<[u8]>,
-    kdf_salt: impl AsRef<[u8]>,
-) -> std::option::Option<String> {
-    //
-    let derived_key = derive_key(master_password, kdf_salt);
-
-    let key = Key::<Aes256Gcm>::from_slice(&derived_key);
-    let cipher = Aes256Gcm::new(key);
+    associated_data: &[u8],
+) -> Result<Option<String>> {
+    let cipher = Aes256Gcm::new(AesKey::<Aes256Gcm>::from_slice(key.as_bytes()));
 
     // same thing with not dealing with options
-    match data {
-        Some(val) => {
-            // this error should be propagated
-            match cipher.encrypt(GenericArray::from_slice(aes_nonce.as_ref()), val.as_ref()) {
-                Ok(encrypted) => Some(hex::encode(encrypted)),
-                Err(_) => todo!(),
-            }
-            // encoding should not really take place here
-        }
-        None => None,
-    }
+    let Some(val) = data else {
+        return Ok(None);
+    };
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+    let payload = Payload {
+        msg: val.as_ref(),
+        aad: associated_data,
+    };
+    // aes-gcm only fails here if the data is too long for a single message
+    let encrypted = cipher
+        .encrypt(&nonce, payload)
+        .map_err(|_| Error::Encoding("data is too long to encrypt".to_string()))?;
+    let mut blob = nonce.to_vec();
+    blob.extend_from_slice(&encrypted);
+    // encoding should not really take place here
+    Ok(Some(hex::encode(blob)))
 }
+/// Decrypts a hex encoded `nonce || ciphertext` blob produced by `encrypt()`.
+/// `associated_data` has to match what the data was encrypted with.
+/// Fails with `Error::Decryption` if the key or associated data is wrong, or the data has been tampered with.
 pub fn decrypt(
-    master_password: impl AsRef<[u8]>,
+    key: &Key,
     data: Option<impl AsRef<[u8]>>, // this function should not even take in an optional parameter
-    aes_nonce: impl AsRef<[u8]>,
-    kdf_salt: impl AsRef<[u8]>,
-) -> Option<String> {
-    //
-    let derived_key = derive_key(master_password, kdf_salt);
-
-    let key = Key::<Aes256Gcm>::from_slice(&derived_key);
-    let cipher = Aes256Gcm::new(key);
+    associated_data: &[u8],
+) -> Result<Option<SecretString>> {
     // same thing with not dealing with options
-    match data {
-        Some(data) => {
-            // this error should be propagated
-            // just a note: since we're doing all this decode+encode nonsense
-            // decoding has to happen first because that's how the data is read from the database,
-            // then once it's been decoded we can decrypt the data
-            let decoded = hex::decode(data).expect("error decoding data");
-            let decoded_nonce = hex::decode(aes_nonce).expect("error decoding nonce.");
-            let decrypted = cipher
-                .decrypt(GenericArray::from_slice(&decoded_nonce), decoded.as_ref())
-                .expect("error decrypting");
-            Some(String::from_utf8(decrypted).expect("Error converting to string"))
+    let Some(data) = data else {
+        return Ok(None);
+    };
+    // just a note: since we're doing all this decode+encode nonsense
+    // decoding has to happen first because that's how the data is read from the database,
+    // then once it's been decoded we can decrypt the data
+    let decoded = hex::decode(data)?;
+    if decoded.len() <= NONCE_LENGTH {
+        return Err(Error::Decryption);
+    }
+    let (nonce, ciphertext) = decoded.split_at(NONCE_LENGTH);
+    let decrypted = decrypt_with_nonce(key, ciphertext, nonce, associated_data)?;
+    Ok(Some(into_secret_string(decrypted)?))
+}
+/// Decrypts data stored in the old format, where every field of a record shared one hex encoded nonce.
+/// This is only needed to read records that haven't been re-encrypted yet.
+pub fn decrypt_legacy(
+    key: &Key,
+    data: Option<impl AsRef<[u8]>>,
+    aes_nonce: impl AsRef<[u8]>,
+) -> Result<Option<SecretString>> {
+    let Some(data) = data else {
+        return Ok(None);
+    };
+    let decoded = hex::decode(data)?;
+    let decoded_nonce = hex::decode(aes_nonce)?;
+    if decoded_nonce.len() != NONCE_LENGTH {
+        return Err(Error::Encoding("invalid nonce length".to_string()));
+    }
+    let decrypted = decrypt_with_nonce(key, &decoded, &decoded_nonce, &[])?;
+    Ok(Some(into_secret_string(decrypted)?))
+}
+// the plaintext is wiped once it's dropped, even if it turns out not to be valid UTF-8
+fn into_secret_string(mut plaintext: Zeroizing<Vec<u8>>) -> Result<SecretString> {
+    match String::from_utf8(std::mem::take(&mut *plaintext)) {
+        Ok(text) => Ok(SecretString::new(text)),
+        Err(e) => {
+            let message = e.utf8_error().to_string();
+            e.into_bytes().zeroize();
+            Err(Error::Encoding(message))
         }
-        None => None,
     }
 }
-/// generates a password given a length using randomness from the OS
-pub fn generate_password(length: usize) -> String {
-    let characters: Vec<char> = "abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ1234567890!@#$%^&*()~`-=_+[]{}\\|;':\",.<>/?".chars().collect();
-    // wouldn't it be lovely if all of my code was this well-written?
-    // this code only looks like this because i didn't write it.
-    (0..length)
-        .map(|_| characters[OsRng.gen_range(0..characters.len())])
-        .collect()
+fn decrypt_with_nonce(
+    key: &Key,
+    ciphertext: &[u8],
+    aes_nonce: &[u8],
+    associated_data: &[u8],
+) -> Result<Zeroizing<Vec<u8>>> {
+    let cipher = Aes256Gcm::new(AesKey::<Aes256Gcm>::from_slice(key.as_bytes()));
+
+    let payload = Payload {
+        msg: ciphertext,
+        aad: associated_data,
+    };
+    cipher
+        .decrypt(GenericArray::from_slice(aes_nonce), payload)
+        .map(Zeroizing::new)
+        .map_err(|_| Error::Decryption)
+}
+/// Generates a new random 256-bit key, used as a vault's data key.
+pub fn generate_key() -> Key {
+    let mut key = Key::zeroed();
+    OsRng.fill(key.as_mut_bytes());
+    key
+}
+/// Encrypts (wraps) `data_key` under the key encryption key `kek`.
+/// The result is hex encoded `nonce || ciphertext`, just like `encrypt()`.
+pub fn wrap_key(kek: &Key, data_key: &Key) -> Result<String> {
+    Ok(encrypt(kek, Some(data_key.as_bytes()), &[])?.unwrap_or_default())
+}
+/// Decrypts (unwraps) a data key produced by `wrap_key()`.
+/// Fails with `Error::Decryption` if `kek` is wrong or the wrapped key has been tampered with.
+pub fn unwrap_key(kek: &Key, wrapped_key: &str) -> Result<Key> {
+    let decoded = hex::decode(wrapped_key)?;
+    if decoded.len() <= NONCE_LENGTH {
+        return Err(Error::Decryption);
+    }
+    let (nonce, ciphertext) = decoded.split_at(NONCE_LENGTH);
+    let data_key = decrypt_with_nonce(kek, ciphertext, nonce, &[])?;
+    let data_key: &[u8; 32] = data_key
+        .as_slice()
+        .try_into()
+        .map_err(|_| Error::Encoding("wrapped key has the wrong length".to_string()))?;
+    Ok(Key::from_bytes(data_key))
+}
+/// Computes a value that identifies `key` without giving it away: HMAC-SHA256 of a fixed label under the key.
+/// Stored in the vault, so a data key that didn't come from unwrapping (e.g. one from the agent) can be checked.
+pub fn key_check(key: &Key) -> String {
+    let mut mac =
+        <Hmac<Sha256> as Mac>::new_from_slice(key.as_bytes()).expect("HMAC accepts any key length");
+    mac.update(b"pwd-rs:key-check");
+    hex::encode(mac.finalize().into_bytes())
 }
+/// The form of an entry name that's compared when looking entries up: trimmed and lowercased,
+/// so "GitHub" and " github " are the same entry.
+pub fn normalize_name(entry_name: &str) -> String {
+    entry_name.trim().to_lowercase()
+}
+/// Computes the blind index for an entry name, used to look entries up without storing their names in plaintext.
+/// The name is normalized (see `normalize_name()`), then hashed with HMAC-SHA256 under a subkey of `key` (the vault's data key),
+/// so anyone holding only the database can't tell which names are in it.
+pub fn blind_index(key: &Key, entry_name: &str) -> String {
+    let mut mac =
+        <Hmac<Sha256> as Mac>::new_from_slice(key.as_bytes()).expect("HMAC accepts any key length");
+    mac.update(b"pwd-rs:name-index");
+    let mut index_key = mac.finalize().into_bytes();
 
+    let mut mac =
+        <Hmac<Sha256> as Mac>::new_from_slice(&index_key).expect("HMAC accepts any key length");
+    index_key.as_mut_slice().zeroize();
+    let normalized = Zeroizing::new(normalize_name(entry_name));
+    mac.update(normalized.as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
 #[cfg(test)]
 mod tests {
-    use aes_gcm::{aead::Aead, aead::OsRng, AeadCore, Aes256Gcm, Key, KeyInit};
+    use aes_gcm::{
+        aead::generic_array::GenericArray, aead::Aead, aead::OsRng, aead::Payload, AeadCore,
+        Aes256Gcm, Key, KeyInit,
+    };
+
+    use crate::secret;
 
     #[test]
     fn sha512() {
@@ -126,52 +252,196 @@ mod tests {
         assert_eq!(res, expected);
     }
     #[test]
-    fn derive_key() {
-        let res = super::derive_key("mymasterpassword", "salt");
+    fn master_password_verifier() {
+        let verifier = super::hash_master_password("mymasterpassword").unwrap();
+        assert!(verifier.starts_with("$argon2id$"));
+        assert!(!super::is_legacy_verifier(&verifier));
+        assert!(super::verify_master_password("mymasterpassword", &verifier).unwrap());
+        assert!(!super::verify_master_password("randomguess", &verifier).unwrap());
+        // a corrupt verifier is an error rather than a panic
+        assert!(super::verify_master_password(
+            "mymasterpassword",
+            "$argon2id$v=19$m=19456,t=2,p=1$!!!$!!!"
+        )
+        .is_err());
+    }
+    #[test]
+    fn master_password_verifier_is_salted() {
+        // the same password should never produce the same verifier twice
+        assert_ne!(
+            super::hash_master_password("mymasterpassword").unwrap(),
+            super::hash_master_password("mymasterpassword").unwrap()
+        );
+    }
+    #[test]
+    fn constant_time_eq() {
+        assert!(super::constant_time_eq(b"hunter2", b"hunter2"));
+        assert!(!super::constant_time_eq(b"hunter2", b"hunter3"));
+        assert!(!super::constant_time_eq(b"hunter2", b"hunter"));
+        assert!(!super::constant_time_eq(b"", b"hunter2"));
+        assert!(super::constant_time_eq(b"", b""));
+    }
+    #[test]
+    fn derive_legacy_key() {
+        let res = super::derive_legacy_key("mymasterpassword", "salt").unwrap();
         let expected =
             hex::decode("8f21affeb61e304e7b474229ffeb34309ed31beda58d153bc7ad9da6e9b6184c")
                 .unwrap();
-        assert_eq!(res.to_vec(), expected);
+        assert_eq!(res.as_bytes().to_vec(), expected);
     }
     #[test]
     fn encrypt() {
-        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
-        // function to test
-        let res = super::encrypt("mymasterpassword", Some("data"), nonce, "salt");
-
         // sourced from: https://neurotechnics.com/tools/pbkdf2-test
         // hex::decode() will decode into an array and then create an encryption key for us to compare to
-        let key = hex::decode("8f21affeb61e304e7b474229ffeb34309ed31beda58d153bc7ad9da6e9b6184c")
+        let key: [u8; 32] =
+            hex_literal::hex!("8f21affeb61e304e7b474229ffeb34309ed31beda58d153bc7ad9da6e9b6184c");
+
+        // function to test
+        let aad = super::associated_data(1, "pass", 4);
+        let res = super::encrypt(&secret::Key::from_bytes(&key), Some("data"), &aad)
+            .unwrap()
             .unwrap();
+        let res = hex::decode(res).unwrap();
+        // the nonce is stored in front of the ciphertext
+        let (nonce, ciphertext) = res.split_at(12);
+
         // manually creating this key/cipher
         let key = Key::<Aes256Gcm>::from_slice(&key);
         let cipher = Aes256Gcm::new(key);
 
-        // encrypt and compare!
-        let ciphertext = cipher.encrypt(&nonce, b"data".as_ref()).unwrap();
+        // decrypt and compare!
+        let payload = Payload {
+            msg: ciphertext,
+            aad: b"pwd-rs:4:1:pass",
+        };
+        let plaintext = cipher
+            .decrypt(GenericArray::from_slice(nonce), payload)
+            .unwrap();
 
-        assert_eq!(res.unwrap(), hex::encode(ciphertext));
+        assert_eq!(plaintext, b"data");
+    }
+    #[test]
+    fn encrypt_uses_unique_nonces() {
+        let key = secret::Key::from_bytes(&[7u8; 32]);
+        let first = super::encrypt(&key, Some("data"), &[]).unwrap().unwrap();
+        let second = super::encrypt(&key, Some("data"), &[]).unwrap().unwrap();
+        assert_ne!(first[..24], second[..24]);
     }
     #[test]
     fn decrypt() {
         let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
-        let key = hex::decode("8f21affeb61e304e7b474229ffeb34309ed31beda58d153bc7ad9da6e9b6184c")
-            .unwrap();
+        let raw_key = [7u8; 32];
         // manually creating this key/cipher
-        let key = Key::<Aes256Gcm>::from_slice(&key);
+        let key = Key::<Aes256Gcm>::from_slice(&raw_key);
         let cipher = Aes256Gcm::new(key);
 
-        let ciphertext = hex::encode(cipher.encrypt(&nonce, b"data".as_ref()).unwrap());
+        let mut blob = nonce.to_vec();
+        blob.extend(cipher.encrypt(&nonce, b"data".as_ref()).unwrap());
 
         // here's the function we're testing
         let result = super::decrypt(
-            "mymasterpassword",
+            &secret::Key::from_bytes(&raw_key),
+            Some(hex::encode(blob)),
+            &[],
+        )
+        .unwrap()
+        .unwrap();
+
+        assert_eq!(result.expose_secret(), "data");
+    }
+    #[test]
+    fn decrypt_with_associated_data() {
+        let key = secret::Key::from_bytes(&[7u8; 32]);
+        let aad = super::associated_data(1, "pass", 4);
+        let ciphertext = super::encrypt(&key, Some("data"), &aad).unwrap();
+        assert_eq!(
+            super::decrypt(&key, ciphertext, &aad)
+                .unwrap()
+                .unwrap()
+                .expose_secret(),
+            "data"
+        );
+    }
+    #[test]
+    fn decrypt_with_wrong_associated_data() {
+        let key = secret::Key::from_bytes(&[7u8; 32]);
+        let ciphertext =
+            super::encrypt(&key, Some("data"), &super::associated_data(1, "pass", 4)).unwrap();
+        // same record, different field
+        assert!(matches!(
+            super::decrypt(&key, ciphertext, &super::associated_data(1, "notes", 4)),
+            Err(crate::Error::Decryption)
+        ));
+    }
+    #[test]
+    fn decrypt_corrupt_data() {
+        let key = secret::Key::from_bytes(&[7u8; 32]);
+        assert!(matches!(
+            super::decrypt(&key, Some("not hex"), &[]),
+            Err(crate::Error::Encoding(_))
+        ));
+        // valid hex, but too short to hold a nonce
+        assert!(matches!(
+            super::decrypt(&key, Some("abcd"), &[]),
+            Err(crate::Error::Decryption)
+        ));
+    }
+    #[test]
+    fn wrap_and_unwrap_key() {
+        let kek = super::generate_key();
+        let data_key = super::generate_key();
+        assert_ne!(kek, data_key);
+
+        let wrapped = super::wrap_key(&kek, &data_key).unwrap();
+        assert_eq!(super::unwrap_key(&kek, &wrapped).unwrap(), data_key);
+        // the wrong key encryption key can't unwrap it
+        assert!(matches!(
+            super::unwrap_key(&super::generate_key(), &wrapped),
+            Err(crate::Error::Decryption)
+        ));
+    }
+    #[test]
+    fn blind_index() {
+        let key = super::generate_key();
+        let index = super::blind_index(&key, "GitHub");
+        // names are normalized before hashing
+        assert_eq!(index, super::blind_index(&key, " github "));
+        assert_ne!(index, super::blind_index(&key, "gitlab"));
+        // and the index depends on the vault's key
+        assert_ne!(index, super::blind_index(&super::generate_key(), "github"));
+        assert!(!index.contains("github"));
+    }
+    #[test]
+    fn key_check() {
+        let key = super::generate_key();
+        assert_eq!(super::key_check(&key), super::key_check(&key.clone()));
+        assert_ne!(
+            super::key_check(&key),
+            super::key_check(&super::generate_key())
+        );
+        // it isn't the name index of anything
+        assert_ne!(
+            super::key_check(&key),
+            super::blind_index(&key, "pwd-rs:key-check")
+        );
+    }
+    #[test]
+    fn decrypt_legacy() {
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let raw_key = [7u8; 32];
+        let key = Key::<Aes256Gcm>::from_slice(&raw_key);
+        let cipher = Aes256Gcm::new(key);
+
+        let ciphertext = hex::encode(cipher.encrypt(&nonce, b"data".as_ref()).unwrap());
+
+        let result = super::decrypt_legacy(
+            &secret::Key::from_bytes(&raw_key),
             Some(ciphertext),
             hex::encode(nonce),
-            "salt",
         )
+        .unwrap()
         .unwrap();
 
-        assert_eq!(result, "data");
+        assert_eq!(result.expose_secret(), "data");
     }
 }
@@ -0,0 +1,149 @@
+// the error type returned by everything in pwd-rs that can fail
+
+use std::fmt;
+
+/// Everything that can go wrong while working with a vault.
+#[derive(Debug)]
+pub enum Error {
+    /// The database couldn't be opened, or a query failed
+    Database(Box<dyn std::error::Error + Send + Sync>),
+    /// Data couldn't be decrypted, because the key is wrong or the data has been tampered with
+    Decryption,
+    /// The master password is incorrect
+    Authentication,
+    /// Stored data isn't in the expected format (bad hex, invalid UTF-8, malformed headers, ...)
+    Encoding(String),
+    /// Something that was looked up doesn't exist, e.g. a record or the vault itself
+    NotFound(String),
+    /// Something that has to be unique already exists, e.g. a record with the same name
+    AlreadyExists(String),
+    /// Reading or writing a file failed
+    Io(std::io::Error),
+    /// The environment or the vault's settings are invalid, e.g. DATABASE_URL isn't set
+    Config(String),
+    /// Arguments passed in by the user are invalid
+    InvalidInput(String),
+}
+
+/// Shorthand for `std::result::Result<T, pwd_rs::Error>`.
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Error {
+    /// The process exit code for this kind of error, so scripts can tell them apart.
+    /// 0 is success, and arguments that can't be parsed exit with `InvalidInput`'s code.
+    /// These are listed in `pwd-rs --help` and shouldn't change.
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            Error::NotFound(_) => 1,
+            Error::Authentication => 2,
+            Error::AlreadyExists(_) => 3,
+            Error::Database(_) => 4,
+            Error::Decryption => 5,
+            Error::InvalidInput(_) => 6,
+            Error::Io(_) => 7,
+            Error::Encoding(_) => 8,
+            Error::Config(_) => 9,
+        }
+    }
+    /// A short, stable name for this kind of error, used in JSON output.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Error::Database(_) => "database",
+            Error::InvalidInput(_) => "invalid_input",
+            Error::Authentication => "authentication",
+            Error::Decryption => "decryption",
+            Error::NotFound(_) => "not_found",
+            Error::AlreadyExists(_) => "already_exists",
+            Error::Io(_) => "io",
+            Error::Encoding(_) => "encoding",
+            Error::Config(_) => "config",
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Database(e) => write!(f, "database error: {}", e),
+            Error::Decryption => write!(
+                f,
+                "could not decrypt data: the key is wrong or the data has been tampered with"
+            ),
+            Error::Authentication => write!(f, "incorrect master password"),
+            Error::Encoding(what) => write!(f, "invalid data: {}", what),
+            Error::NotFound(what) => write!(f, "{} not found", what),
+            Error::AlreadyExists(what) => write!(f, "{} already exists", what),
+            Error::Io(e) => write!(f, "i/o error: {}", e),
+            Error::Config(what) => write!(f, "configuration error: {}", what),
+            Error::InvalidInput(what) => write!(f, "invalid input: {}", what),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Database(e) => Some(e.as_ref()),
+            Error::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+// diesel's NotFound and unique constraint violations get their own variants,
+// everything else is just a database error
+impl From<diesel::result::Error> for Error {
+    fn from(e: diesel::result::Error) -> Self {
+        use diesel::result::DatabaseErrorKind;
+        match e {
+            diesel::result::Error::NotFound => Error::NotFound("record".to_string()),
+            diesel::result::Error::DatabaseError(DatabaseErrorKind::UniqueViolation, _) => {
+                Error::AlreadyExists("record".to_string())
+            }
+            e => Error::Database(Box::new(e)),
+        }
+    }
+}
+
+impl From<diesel::ConnectionError> for Error {
+    fn from(e: diesel::ConnectionError) -> Self {
+        Error::Database(Box::new(e))
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+impl From<hex::FromHexError> for Error {
+    fn from(e: hex::FromHexError) -> Self {
+        Error::Encoding(e.to_string())
+    }
+}
+
+impl From<std::string::FromUtf8Error> for Error {
+    fn from(e: std::string::FromUtf8Error) -> Self {
+        Error::Encoding(e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Error;
+
+    // scripts rely on these, see the table in `pwd-rs --help`
+    #[test]
+    fn exit_codes() {
+        assert_eq!(Error::NotFound("record".to_string()).exit_code(), 1);
+        assert_eq!(Error::Authentication.exit_code(), 2);
+        assert_eq!(Error::AlreadyExists("record".to_string()).exit_code(), 3);
+        assert_eq!(Error::Database("locked".into()).exit_code(), 4);
+        assert_eq!(Error::Decryption.exit_code(), 5);
+        assert_eq!(Error::InvalidInput("".to_string()).exit_code(), 6);
+        assert_eq!(Error::Io(std::io::ErrorKind::Other.into()).exit_code(), 7);
+        assert_eq!(Error::Encoding("".to_string()).exit_code(), 8);
+        assert_eq!(Error::Config("".to_string()).exit_code(), 9);
+    }
+}
@@ -0,0 +1,944 @@
+// generating random passwords that follow a policy, since a lot of sites are picky about which characters they accept,
+// passphrases made of random words, which are easier to type and remember, PINs and pronounceable passwords.
+// every mode picks uniformly at random with `pick()`, so their entropy can be worked out exactly and compared.
+
+use std::path::{Path, PathBuf};
+
+use rand::distributions::WeightedIndex;
+use rand::seq::SliceRandom;
+use rand::{rngs::OsRng, Rng};
+use zeroize::Zeroize;
+
+use crate::secret::SecretString;
+use crate::{Error, Result};
+
+const LOWERCASE: &str = "abcdefghijklmnopqrstuvwxyz";
+const UPPERCASE: &str = "ABCDEFGHIJKLMNOPQRSTUVWXYZ";
+const DIGITS: &str = "0123456789";
+/// The symbols used unless a policy has its own set.
+pub const DEFAULT_SYMBOLS: &str = "!@#$%^&*()~`-=_+[]{}\\|;':\",.<>/?";
+/// Characters that are easily mistaken for each other, left out with `--exclude-ambiguous`.
+pub const AMBIGUOUS: &str = "0O1lI|";
+
+// how many candidates are generated before giving up on a policy that's (almost) impossible to satisfy
+const MAX_ATTEMPTS: usize = 100_000;
+
+// the bundled wordlist: 7776 short, common words that are easy to type and tell apart,
+// numbered with dice rolls like the EFF's large wordlist (https://www.eff.org/dice), so it works with real dice too.
+// it is NOT the EFF's list (many words differ), to use that one, download it and pass it with --wordlist
+const LARGE_WORDLIST: &str = include_str!("wordlists/large_wordlist.txt");
+
+/// A generated password, and how hard it is to guess.
+#[derive(Debug)]
+pub struct Generated {
+    pub password: SecretString,
+    /// How many guesses it takes to try every password that could have been generated, as a power of 2,
+    /// for an attacker who knows exactly how it was generated
+    pub entropy_bits: f64,
+}
+
+// picks one of `items` uniformly, using randomness from the OS. gen_range rejects values that would make
+// some items more likely than others, so this doesn't have the bias of `random_number % items.len()`
+fn pick<T>(items: &[T]) -> &T {
+    &items[OsRng.gen_range(0..items.len())]
+}
+
+// log2(2^a + 2^b), for adding up counts that are too big for an f64 as logarithms
+fn log2_add(a: f64, b: f64) -> f64 {
+    let (high, low) = if a > b { (a, b) } else { (b, a) };
+    if low == f64::NEG_INFINITY {
+        return high;
+    }
+    high + (1.0 + (low - high).exp2()).log2()
+}
+
+// log2(n!) for every n up to `max`
+fn log2_factorials(max: usize) -> Vec<f64> {
+    (0..=max)
+        .scan(0.0, |sum, n| {
+            *sum += (n.max(1) as f64).log2();
+            Some(*sum)
+        })
+        .collect()
+}
+
+// log2 of C(n, k), using the table from log2_factorials()
+fn log2_binomial(log2_factorial: &[f64], n: usize, k: usize) -> f64 {
+    log2_factorial[n] - log2_factorial[n - k] - log2_factorial[k]
+}
+
+/// What a generated password has to look like.
+#[derive(Clone, Debug, PartialEq, Eq, clap::Args)]
+pub struct GeneratorPolicy {
+    /// Password length
+    #[arg(short, long, default_value_t = 10)]
+    pub length: usize,
+    /// Don't use lowercase letters
+    #[arg(long = "no-lowercase", action = clap::ArgAction::SetFalse)]
+    pub lowercase: bool,
+    /// Don't use uppercase letters
+    #[arg(long = "no-uppercase", action = clap::ArgAction::SetFalse)]
+    pub uppercase: bool,
+    /// Don't use digits
+    #[arg(long = "no-digits", action = clap::ArgAction::SetFalse)]
+    pub digits: bool,
+    /// Don't use symbols
+    #[arg(long = "no-symbols", action = clap::ArgAction::SetFalse)]
+    pub symbols: bool,
+    /// Only use these symbols, for sites that reject some of them
+    #[arg(
+        long = "symbol-set",
+        value_name = "SYMBOLS",
+        allow_hyphen_values = true,
+        conflicts_with = "symbols"
+    )]
+    pub symbol_set: Option<String>,
+    /// Leave out characters that are easily mistaken for each other (0O1lI|)
+    #[arg(long)]
+    pub exclude_ambiguous: bool,
+    /// At least this many lowercase letters
+    #[arg(long, value_name = "COUNT", default_value_t = 0)]
+    pub min_lowercase: usize,
+    /// At least this many uppercase letters
+    #[arg(long, value_name = "COUNT", default_value_t = 0)]
+    pub min_uppercase: usize,
+    /// At least this many digits
+    #[arg(long, value_name = "COUNT", default_value_t = 0)]
+    pub min_digits: usize,
+    /// At least this many symbols
+    #[arg(long, value_name = "COUNT", default_value_t = 0)]
+    pub min_symbols: usize,
+    /// Never use the same character twice in a row
+    #[arg(long)]
+    pub no_repeats: bool,
+}
+
+impl Default for GeneratorPolicy {
+    fn default() -> Self {
+        GeneratorPolicy {
+            length: 10,
+            lowercase: true,
+            uppercase: true,
+            digits: true,
+            symbols: true,
+            symbol_set: None,
+            exclude_ambiguous: false,
+            min_lowercase: 0,
+            min_uppercase: 0,
+            min_digits: 0,
+            min_symbols: 0,
+            no_repeats: false,
+        }
+    }
+}
+
+// one of the character classes a policy is made of
+struct Class {
+    name: &'static str,
+    characters: Vec<char>,
+    min: usize,
+}
+
+impl Class {
+    // log2 of how many strings of `count` characters from this class there are
+    fn log2_size(&self, count: usize) -> f64 {
+        count as f64 * (self.characters.len() as f64).log2()
+    }
+}
+
+impl GeneratorPolicy {
+    // the enabled classes, without the excluded characters.
+    // they don't overlap, even if a custom symbol set has letters or digits in it
+    fn classes(&self) -> Vec<Class> {
+        let symbols = self.symbol_set.as_deref().unwrap_or(DEFAULT_SYMBOLS);
+        let mut seen: Vec<char> = Vec::new();
+        [
+            (
+                "lowercase letters",
+                LOWERCASE,
+                self.lowercase,
+                self.min_lowercase,
+            ),
+            (
+                "uppercase letters",
+                UPPERCASE,
+                self.uppercase,
+                self.min_uppercase,
+            ),
+            ("digits", DIGITS, self.digits, self.min_digits),
+            ("symbols", symbols, self.symbols, self.min_symbols),
+        ]
+        .into_iter()
+        .filter(|(_, _, enabled, _)| *enabled)
+        .map(|(name, characters, _, min)| {
+            let mut characters: Vec<char> = characters
+                .chars()
+                .filter(|c| !(self.exclude_ambiguous && AMBIGUOUS.contains(*c)))
+                .filter(|c| !seen.contains(c))
+                .collect();
+            // a custom set could list a character twice, which would make it more likely
+            characters.sort_unstable();
+            characters.dedup();
+            seen.extend(&characters);
+            Class {
+                name,
+                characters,
+                min,
+            }
+        })
+        .collect()
+    }
+
+    /// Checks that the policy can be satisfied at all, e.g. that the minimums fit in the length.
+    pub fn validate(&self) -> Result<()> {
+        let classes = self.classes();
+        if self.length == 0 {
+            return Err(Error::InvalidInput(
+                "password length has to be at least 1".to_string(),
+            ));
+        }
+        if classes.is_empty() {
+            return Err(Error::InvalidInput(
+                "every character class is disabled".to_string(),
+            ));
+        }
+        // minimums for disabled classes can never be met
+        let disabled = [
+            ("lowercase letters", self.lowercase, self.min_lowercase),
+            ("uppercase letters", self.uppercase, self.min_uppercase),
+            ("digits", self.digits, self.min_digits),
+            ("symbols", self.symbols, self.min_symbols),
+        ];
+        for (name, enabled, min) in disabled {
+            if !enabled && min > 0 {
+                return Err(Error::InvalidInput(format!(
+                    "a minimum is set for {}, but they're disabled",
+                    name
+                )));
+            }
+        }
+        for class in &classes {
+            if class.characters.is_empty() {
+                return Err(Error::InvalidInput(format!(
+                    "there are no {} left to choose from",
+                    class.name
+                )));
+            }
+        }
+        let min_length: usize = classes.iter().map(|class| class.min).sum();
+        if min_length > self.length {
+            return Err(Error::InvalidInput(format!(
+                "the minimums add up to {} characters, but the length is {}",
+                min_length, self.length
+            )));
+        }
+        if self.no_repeats && self.alphabet().len() < 2 && self.length > 1 {
+            return Err(Error::InvalidInput(
+                "repeats can't be avoided with a single character".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Every character a password can contain.
+    pub fn alphabet(&self) -> Vec<char> {
+        self.classes()
+            .into_iter()
+            .flat_map(|class| class.characters)
+            .collect()
+    }
+
+    // counts[i][k] is log2 of how many k character strings made of the first i classes meet their minimums.
+    // passwords are their classes' characters interleaved: with counts[i][k] strings of k characters,
+    // adding c characters of the next class makes C(k + c, c) * size^c times as many
+    fn class_counts(&self, classes: &[Class], log2_factorial: &[f64]) -> Vec<Vec<f64>> {
+        let length = self.length;
+        let mut counts = vec![f64::NEG_INFINITY; length + 1];
+        counts[0] = 0.0;
+        let mut table = vec![counts];
+        for class in classes {
+            let counts = &table[table.len() - 1];
+            let mut next = vec![f64::NEG_INFINITY; length + 1];
+            for k in 0..=length {
+                for c in class.min..=length - k {
+                    next[k + c] = log2_add(
+                        next[k + c],
+                        counts[k] + log2_binomial(log2_factorial, k + c, c) + class.log2_size(c),
+                    );
+                }
+            }
+            table.push(next);
+        }
+        table
+    }
+
+    /// The entropy of passwords generated with this policy, in bits.
+    ///
+    /// It's exact, except with `no_repeats` and minimums together, where it's a close estimate.
+    pub fn entropy_bits(&self) -> f64 {
+        let classes = self.classes();
+        let alphabet = classes
+            .iter()
+            .map(|class| class.characters.len())
+            .sum::<usize>() as f64;
+        let length = self.length;
+        let counts = self.class_counts(&classes, &log2_factorials(length));
+        let mut entropy_bits = counts[classes.len()][length];
+        // after the first character, one of the characters can't come next
+        if self.no_repeats && length > 1 {
+            entropy_bits += (length - 1) as f64 * ((alphabet - 1.0) / alphabet).log2();
+        }
+        entropy_bits
+    }
+
+    // how many characters of each class a password gets. working back from the last class,
+    // each count is picked in proportion to how many passwords have it, using the table from class_counts()
+    fn pick_class_counts(
+        &self,
+        classes: &[Class],
+        counts: &[Vec<f64>],
+        log2_factorial: &[f64],
+    ) -> Result<Vec<usize>> {
+        let mut remaining = self.length;
+        let mut picked = vec![0; classes.len()];
+        for (i, class) in classes.iter().enumerate().rev() {
+            let options: Vec<(usize, f64)> = (class.min..=remaining)
+                .map(|c| {
+                    let ways = counts[i][remaining - c]
+                        + log2_binomial(log2_factorial, remaining, c)
+                        + class.log2_size(c);
+                    (c, ways)
+                })
+                .collect();
+            // relative to the most likely count, so the weights fit in an f64
+            let most = options
+                .iter()
+                .map(|(_, ways)| *ways)
+                .fold(f64::NEG_INFINITY, f64::max);
+            let weights = WeightedIndex::new(options.iter().map(|(_, ways)| (ways - most).exp2()))
+                .map_err(|_| Error::InvalidInput("no password satisfies the policy".to_string()))?;
+            picked[i] = options[OsRng.sample(&weights)].0;
+            remaining -= picked[i];
+        }
+        Ok(picked)
+    }
+
+    /// Generates a password that satisfies the policy, using randomness from the OS.
+    ///
+    /// How many characters of each class it gets is picked first, weighted by how many passwords have those counts,
+    /// then the characters are picked uniformly from their classes and shuffled. That makes every password
+    /// that satisfies the minimums equally likely, without retrying. Only `no_repeats` is handled by throwing
+    /// candidates away and generating them again. Invalid policies fail with `Error::InvalidInput`.
+    pub fn generate(&self) -> Result<Generated> {
+        self.validate()?;
+        let classes = self.classes();
+        let log2_factorial = log2_factorials(self.length);
+        let counts = self.class_counts(&classes, &log2_factorial);
+        for _ in 0..MAX_ATTEMPTS {
+            let mut candidate = Vec::with_capacity(self.length);
+            let picked = self.pick_class_counts(&classes, &counts, &log2_factorial)?;
+            for (class, count) in classes.iter().zip(picked) {
+                candidate.extend((0..count).map(|_| *pick(&class.characters)));
+            }
+            // a Fisher-Yates shuffle
+            candidate.shuffle(&mut OsRng);
+            let accepted = !self.no_repeats || candidate.windows(2).all(|pair| pair[0] != pair[1]);
+            let password = accepted.then(|| candidate.iter().collect());
+            candidate.zeroize();
+            if let Some(password) = password {
+                return Ok(Generated {
+                    password: SecretString::new(password),
+                    entropy_bits: self.entropy_bits(),
+                });
+            }
+        }
+        Err(Error::InvalidInput(
+            "the password policy is too strict, try a longer password or fewer constraints"
+                .to_string(),
+        ))
+    }
+}
+
+/// How the words of a passphrase are capitalized.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum Capitalization {
+    /// Leave words as they are in the wordlist
+    #[default]
+    None,
+    /// Capitalize the first letter of every word
+    First,
+    /// Capitalize every letter
+    All,
+}
+
+/// What a generated passphrase has to look like.
+#[derive(Clone, Debug, PartialEq, Eq, clap::Args)]
+pub struct PassphrasePolicy {
+    /// Number of words
+    #[arg(short, long, default_value_t = 6)]
+    pub words: usize,
+    /// Put between words
+    #[arg(short, long, default_value = "-", allow_hyphen_values = true)]
+    pub separator: String,
+    /// How words are capitalized
+    #[arg(short, long, value_enum, default_value_t = Capitalization::None)]
+    pub capitalize: Capitalization,
+    /// Append a random digit, for sites that require one
+    #[arg(long)]
+    pub digit: bool,
+    /// Append a random symbol, for sites that require one
+    #[arg(long)]
+    pub symbol: bool,
+    /// Pick words from this file instead of the bundled 7776 word list (which isn't the EFF's list).
+    /// It has one word per line, dice rolls in front of the words (like in the EFF's lists) are ignored
+    #[arg(long, value_name = "PATH")]
+    pub wordlist: Option<PathBuf>,
+}
+
+impl Default for PassphrasePolicy {
+    fn default() -> Self {
+        PassphrasePolicy {
+            words: 6,
+            separator: "-".to_string(),
+            capitalize: Capitalization::None,
+            digit: false,
+            symbol: false,
+            wordlist: None,
+        }
+    }
+}
+
+// the last word of every line, without duplicates, which would make some words more likely
+fn parse_wordlist(text: &str) -> Vec<String> {
+    let mut words: Vec<String> = text
+        .lines()
+        .filter_map(|line| line.split_whitespace().last())
+        .map(|word| word.to_string())
+        .collect();
+    words.sort_unstable();
+    words.dedup();
+    words
+}
+
+/// Reads the wordlist at `path`, or the bundled 7776 word list if there's no path.
+/// The bundled list is laid out like the EFF's large wordlist, but it isn't a copy of it.
+pub fn load_wordlist(path: Option<&Path>) -> Result<Vec<String>> {
+    let words = match path {
+        Some(path) => parse_wordlist(&std::fs::read_to_string(path)?),
+        None => parse_wordlist(LARGE_WORDLIST),
+    };
+    if words.len() < 2 {
+        return Err(Error::InvalidInput(
+            "the wordlist needs at least 2 different words".to_string(),
+        ));
+    }
+    Ok(words)
+}
+
+fn capitalize(word: &str, capitalization: Capitalization) -> String {
+    match capitalization {
+        Capitalization::None => word.to_string(),
+        Capitalization::First => {
+            let mut chars = word.chars();
+            match chars.next() {
+                Some(first) => first.to_uppercase().chain(chars).collect(),
+                None => String::new(),
+            }
+        }
+        Capitalization::All => word.to_uppercase(),
+    }
+}
+
+impl PassphrasePolicy {
+    /// Generates a passphrase from the policy's wordlist, using randomness from the OS.
+    pub fn generate(&self) -> Result<Generated> {
+        let words = load_wordlist(self.wordlist.as_deref())?;
+        self.generate_from(&words)
+    }
+
+    /// Generates a passphrase from `words`, which shouldn't contain duplicates and needs at least 2 of them.
+    /// Every word (and the digit and symbol) is picked uniformly, so the entropy is exact.
+    pub fn generate_from(&self, words: &[String]) -> Result<Generated> {
+        if self.words == 0 {
+            return Err(Error::InvalidInput(
+                "a passphrase needs at least 1 word".to_string(),
+            ));
+        }
+        if words.len() < 2 {
+            return Err(Error::InvalidInput(
+                "the wordlist needs at least 2 different words".to_string(),
+            ));
+        }
+        let mut picked: Vec<String> = (0..self.words)
+            .map(|_| capitalize(pick::<String>(words), self.capitalize))
+            .collect();
+        let mut password = picked.join(&self.separator);
+        picked.zeroize();
+        let mut entropy_bits = self.words as f64 * (words.len() as f64).log2();
+
+        let digits: Vec<char> = DIGITS.chars().collect();
+        let symbols: Vec<char> = DEFAULT_SYMBOLS.chars().collect();
+        for (append, characters) in [(self.digit, &digits), (self.symbol, &symbols)] {
+            if append {
+                password.push(*pick(characters));
+                entropy_bits += (characters.len() as f64).log2();
+            }
+        }
+        Ok(Generated {
+            password: SecretString::new(password),
+            entropy_bits,
+        })
+    }
+}
+
+/// What a generated PIN has to look like.
+#[derive(Clone, Debug, PartialEq, Eq, clap::Args)]
+pub struct PinPolicy {
+    /// Number of digits
+    #[arg(short, long, default_value_t = 6)]
+    pub length: usize,
+    /// Never use the same digit twice in a row
+    #[arg(long)]
+    pub no_repeats: bool,
+    /// Never use three digits in a row that count up or down, like 123 or 987
+    #[arg(long)]
+    pub no_sequences: bool,
+}
+
+impl Default for PinPolicy {
+    fn default() -> Self {
+        PinPolicy {
+            length: 6,
+            no_repeats: false,
+            no_sequences: false,
+        }
+    }
+}
+
+impl PinPolicy {
+    fn is_satisfied_by(&self, pin: &[char]) -> bool {
+        let digits: Vec<i32> = pin
+            .iter()
+            .filter_map(|c| c.to_digit(10))
+            .map(|d| d as i32)
+            .collect();
+        let no_repeats = !self.no_repeats || digits.windows(2).all(|pair| pair[0] != pair[1]);
+        let no_sequences = !self.no_sequences
+            || digits.windows(3).all(|run| {
+                let step = run[1] - run[0];
+                !(step.abs() == 1 && run[2] - run[1] == step)
+            });
+        no_repeats && no_sequences
+    }
+
+    // completions[k][a][b] is how many ways there are to add k more digits to a PIN that ends with a and then b.
+    // they're scaled down after every digit so they don't overflow for long PINs, which doesn't change how they
+    // compare to each other. the second value is the log2 of everything they were scaled down by
+    fn completions(&self) -> (Vec<[[f64; 10]; 10]>, f64) {
+        let mut completions = vec![[[1.0f64; 10]; 10]];
+        let mut scale_bits = 0.0;
+        for _ in 2..self.length {
+            let last = completions[completions.len() - 1];
+            let mut next = [[0.0f64; 10]; 10];
+            for (a, row) in next.iter_mut().enumerate() {
+                for (b, count) in row.iter_mut().enumerate() {
+                    *count = (0..10)
+                        .filter(|&c| self.is_satisfied_by(&[digit(a), digit(b), digit(c)]))
+                        .map(|c| last[b][c])
+                        .sum();
+                }
+            }
+            let total: f64 = next.iter().flatten().sum();
+            if total > 0.0 {
+                scale_bits += total.log2();
+                next = next.map(|row| row.map(|count| count / total));
+            }
+            completions.push(next);
+        }
+        (completions, scale_bits)
+    }
+
+    // how many acceptable PINs start with each pair of digits, scaled down like completions()
+    fn starts(&self, completions: &[[[f64; 10]; 10]]) -> Vec<f64> {
+        let rest = &completions[self.length - 2];
+        (0..100)
+            .map(|i| {
+                let (a, b) = (i / 10, i % 10);
+                if self.is_satisfied_by(&[digit(a), digit(b)]) {
+                    rest[a][b]
+                } else {
+                    0.0
+                }
+            })
+            .collect()
+    }
+
+    /// The entropy of PINs generated with this policy, in bits.
+    /// PINs that break the constraints are counted by which two digits they end with, so it's exact.
+    pub fn entropy_bits(&self) -> f64 {
+        match self.length {
+            0 => 0.0,
+            1 => 10f64.log2(),
+            _ => {
+                let (completions, scale_bits) = self.completions();
+                scale_bits + self.starts(&completions).iter().sum::<f64>().log2()
+            }
+        }
+    }
+
+    /// Generates a PIN that satisfies the policy, using randomness from the OS.
+    /// Every digit is picked in proportion to how many acceptable PINs it leads to,
+    /// so every acceptable PIN is equally likely and generation never fails while there are any.
+    pub fn generate(&self) -> Result<Generated> {
+        if self.length == 0 {
+            return Err(Error::InvalidInput(
+                "a PIN needs at least 1 digit".to_string(),
+            ));
+        }
+        let unsatisfiable = |_| Error::InvalidInput("no PIN satisfies the policy".to_string());
+        let mut pin = Vec::with_capacity(self.length);
+        if self.length == 1 {
+            pin.push(*pick(&DIGITS.chars().collect::<Vec<char>>()));
+        } else {
+            let (completions, _) = self.completions();
+            let start =
+                OsRng.sample(WeightedIndex::new(self.starts(&completions)).map_err(unsatisfiable)?);
+            let (mut a, mut b) = (start / 10, start % 10);
+            pin.extend([digit(a), digit(b)]);
+            for rest in completions[..self.length - 2].iter().rev() {
+                let weights = (0..10).map(|c| {
+                    if self.is_satisfied_by(&[digit(a), digit(b), digit(c)]) {
+                        rest[b][c]
+                    } else {
+                        0.0
+                    }
+                });
+                let c = OsRng.sample(WeightedIndex::new(weights).map_err(unsatisfiable)?);
+                pin.push(digit(c));
+                (a, b) = (b, c);
+            }
+        }
+        let password = SecretString::new(pin.iter().collect());
+        pin.zeroize();
+        Ok(Generated {
+            password,
+            entropy_bits: self.entropy_bits(),
+        })
+    }
+}
+
+fn digit(d: usize) -> char {
+    char::from_digit(d as u32, 10).unwrap_or('0')
+}
+
+// consonants that are hard to mishear or misspell when read aloud (no c, q, w, x or y)
+const PRONOUNCEABLE_CONSONANTS: &str = "bdfghjklmnprstvz";
+const PRONOUNCEABLE_VOWELS: &str = "aeiou";
+
+/// What a generated pronounceable password has to look like.
+#[derive(Clone, Debug, PartialEq, Eq, clap::Args)]
+pub struct PronounceablePolicy {
+    /// Number of letters, which alternate between consonants and vowels
+    #[arg(short, long, default_value_t = 14)]
+    pub length: usize,
+    /// Capitalize the first letter
+    #[arg(short, long)]
+    pub capitalize: bool,
+    /// Append this many random digits, for sites that require them
+    #[arg(short, long, value_name = "COUNT", default_value_t = 0)]
+    pub digits: usize,
+}
+
+impl Default for PronounceablePolicy {
+    fn default() -> Self {
+        PronounceablePolicy {
+            length: 14,
+            capitalize: false,
+            digits: 0,
+        }
+    }
+}
+
+impl PronounceablePolicy {
+    // which characters can go where: consonant and vowel syllables, then the digits
+    fn positions(&self) -> Vec<Vec<char>> {
+        let consonants: Vec<char> = PRONOUNCEABLE_CONSONANTS.chars().collect();
+        let vowels: Vec<char> = PRONOUNCEABLE_VOWELS.chars().collect();
+        let digits: Vec<char> = DIGITS.chars().collect();
+        (0..self.length)
+            .map(|i| match i % 2 {
+                0 => consonants.clone(),
+                _ => vowels.clone(),
+            })
+            .chain((0..self.digits).map(|_| digits.clone()))
+            .collect()
+    }
+
+    /// The entropy of passwords generated with this policy, in bits.
+    /// Capitalizing the first letter doesn't add any, since it always happens.
+    pub fn entropy_bits(&self) -> f64 {
+        self.positions()
+            .iter()
+            .map(|characters| (characters.len() as f64).log2())
+            .sum()
+    }
+
+    /// Generates a pronounceable password, using randomness from the OS.
+    pub fn generate(&self) -> Result<Generated> {
+        if self.length == 0 {
+            return Err(Error::InvalidInput(
+                "a pronounceable password needs at least 1 letter".to_string(),
+            ));
+        }
+        let mut password: String = self
+            .positions()
+            .iter()
+            .map(|characters| *pick(characters))
+            .collect();
+        if self.capitalize {
+            let first = capitalize(&password, Capitalization::First);
+            password.zeroize();
+            password = first;
+        }
+        Ok(Generated {
+            password: SecretString::new(password),
+            entropy_bits: self.entropy_bits(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        load_wordlist, parse_wordlist, Capitalization, GeneratorPolicy, PassphrasePolicy,
+        PinPolicy, PronounceablePolicy, AMBIGUOUS, DIGITS, PRONOUNCEABLE_CONSONANTS,
+        PRONOUNCEABLE_VOWELS, UPPERCASE,
+    };
+    use crate::Error;
+
+    #[test]
+    fn default_policy() {
+        let generated = GeneratorPolicy::default().generate().unwrap();
+        assert_eq!(generated.password.expose_secret().chars().count(), 10);
+        assert_eq!(GeneratorPolicy::default().alphabet().len(), 94);
+        // without any constraints, every character adds log2(94) bits
+        assert!((generated.entropy_bits - 10.0 * 94f64.log2()).abs() < 1e-9);
+    }
+    #[test]
+    fn minimums_are_met() {
+        let policy = GeneratorPolicy {
+            length: 6,
+            min_uppercase: 2,
+            min_digits: 3,
+            no_repeats: true,
+            exclude_ambiguous: true,
+            ..Default::default()
+        };
+        for _ in 0..100 {
+            let generated = policy.generate().unwrap();
+            let password: Vec<char> = generated.password.expose_secret().chars().collect();
+            assert_eq!(password.len(), 6);
+            assert!(password.iter().filter(|c| UPPERCASE.contains(**c)).count() >= 2);
+            assert!(password.iter().filter(|c| DIGITS.contains(**c)).count() >= 3);
+            assert!(password.windows(2).all(|pair| pair[0] != pair[1]));
+            assert!(!password.iter().any(|c| AMBIGUOUS.contains(*c)));
+        }
+    }
+    #[test]
+    fn strict_minimums() {
+        // most passwords don't meet these, which used to make generating them give up
+        for (length, min_digits) in [(10, 8), (8, 6), (4, 4)] {
+            let policy = GeneratorPolicy {
+                length,
+                min_digits,
+                ..Default::default()
+            };
+            for _ in 0..50 {
+                let generated = policy.generate().unwrap();
+                let password = generated.password.expose_secret();
+                assert_eq!(password.len(), length);
+                assert!(password.chars().filter(|c| DIGITS.contains(*c)).count() >= min_digits);
+            }
+        }
+    }
+    #[test]
+    fn class_counts_are_weighted() {
+        // 2 characters out of lowercase letters and digits, with at least 1 digit:
+        // 10 * 10 of the 10 * 10 + 2 * 10 * 26 passwords are all digits
+        let policy = GeneratorPolicy {
+            length: 2,
+            uppercase: false,
+            symbols: false,
+            min_digits: 1,
+            ..Default::default()
+        };
+        let runs = 20_000;
+        let all_digits = (0..runs)
+            .filter(|_| {
+                let generated = policy.generate().unwrap();
+                generated
+                    .password
+                    .expose_secret()
+                    .chars()
+                    .all(|c| DIGITS.contains(c))
+            })
+            .count() as f64;
+        let expected = runs as f64 * 100.0 / 620.0;
+        // about 6 standard deviations
+        assert!((all_digits - expected).abs() < 300.0, "{}", all_digits);
+    }
+    #[test]
+    fn custom_symbols() {
+        let policy = GeneratorPolicy {
+            lowercase: false,
+            uppercase: false,
+            digits: false,
+            symbol_set: Some("-_-".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(policy.alphabet(), vec!['-', '_']);
+        let generated = policy.generate().unwrap();
+        assert!(generated
+            .password
+            .expose_secret()
+            .chars()
+            .all(|c| c == '-' || c == '_'));
+    }
+    #[test]
+    fn invalid_policies() {
+        let too_short = GeneratorPolicy {
+            length: 3,
+            min_digits: 2,
+            min_symbols: 2,
+            ..Default::default()
+        };
+        assert!(too_short.generate().is_err());
+        let nothing_left = GeneratorPolicy {
+            symbol_set: Some("|".to_string()),
+            exclude_ambiguous: true,
+            ..Default::default()
+        };
+        assert!(nothing_left.generate().is_err());
+        let disabled_minimum = GeneratorPolicy {
+            digits: false,
+            min_digits: 1,
+            ..Default::default()
+        };
+        assert!(disabled_minimum.generate().is_err());
+        let everything_disabled = GeneratorPolicy {
+            lowercase: false,
+            uppercase: false,
+            digits: false,
+            symbols: false,
+            ..Default::default()
+        };
+        assert!(everything_disabled.generate().is_err());
+    }
+    #[test]
+    fn wordlists() {
+        // the EFF's format, plain words, blank lines and duplicates
+        let words = parse_wordlist("11111\tabacus\n11112 abdomen\n\nzebra\nabacus\n");
+        assert_eq!(words, vec!["abacus", "abdomen", "zebra"]);
+    }
+    #[test]
+    fn bundled_wordlist() {
+        let words = load_wordlist(None).unwrap();
+        // one word for every roll of 5 dice, so each word is log2(7776) ~ 12.9 bits
+        assert_eq!(words.len(), 7776);
+        assert!(words.iter().all(
+            |word| word.len() >= 3 && word.chars().all(|c| c.is_ascii_lowercase() || c == '-')
+        ));
+    }
+    #[test]
+    fn passphrase() {
+        let words = parse_wordlist("alpha\nbravo\ncharlie\ndelta");
+        let policy = PassphrasePolicy {
+            words: 4,
+            separator: " ".to_string(),
+            capitalize: Capitalization::First,
+            digit: true,
+            ..Default::default()
+        };
+        let generated = policy.generate_from(&words).unwrap();
+        let passphrase = generated.password.expose_secret();
+        let (last, digit) = passphrase.split_at(passphrase.len() - 1);
+        assert!(DIGITS.contains(digit));
+        let picked: Vec<&str> = last.split(' ').collect();
+        assert_eq!(picked.len(), 4);
+        assert!(picked
+            .iter()
+            .all(|word| ["Alpha", "Bravo", "Charlie", "Delta"].contains(word)));
+        // 4 words out of 4 is 8 bits, and a digit is log2(10)
+        assert!((generated.entropy_bits - (8.0 + 10f64.log2())).abs() < 1e-9);
+        assert!(matches!(
+            policy.generate_from(&[]),
+            Err(Error::InvalidInput(_))
+        ));
+    }
+    #[test]
+    fn entropy_with_minimums() {
+        // 2 characters with at least 1 digit: 10 * 10 + 2 * 10 * 84 passwords
+        let policy = GeneratorPolicy {
+            length: 2,
+            min_digits: 1,
+            ..Default::default()
+        };
+        assert!((policy.entropy_bits() - 1780f64.log2()).abs() < 1e-9);
+    }
+    #[test]
+    fn pin() {
+        let policy = PinPolicy {
+            length: 8,
+            no_repeats: true,
+            no_sequences: true,
+        };
+        for _ in 0..100 {
+            let generated = policy.generate().unwrap();
+            let pin: Vec<char> = generated.password.expose_secret().chars().collect();
+            assert_eq!(pin.len(), 8);
+            assert!(pin.iter().all(|c| c.is_ascii_digit()));
+            assert!(policy.is_satisfied_by(&pin));
+        }
+        assert!(!policy.is_satisfied_by(&['1', '2', '3']));
+        assert!(!policy.is_satisfied_by(&['9', '8', '7']));
+        assert!(!policy.is_satisfied_by(&['5', '5']));
+        assert!(policy.is_satisfied_by(&['1', '3', '5']));
+        // far too long for throwing away PINs that break the constraints to ever find one
+        let long = PinPolicy {
+            length: 200,
+            ..policy
+        };
+        let generated = long.generate().unwrap();
+        let pin: Vec<char> = generated.password.expose_secret().chars().collect();
+        assert_eq!(pin.len(), 200);
+        assert!(long.is_satisfied_by(&pin));
+    }
+    #[test]
+    fn pin_entropy() {
+        assert!((PinPolicy::default().entropy_bits() - 6.0 * 10f64.log2()).abs() < 1e-9);
+        // 3 digits without repeats: 10 * 9 * 9 PINs, 16 of which (012 to 789 and 987 to 210) are sequences
+        let policy = PinPolicy {
+            length: 3,
+            no_repeats: true,
+            no_sequences: true,
+        };
+        assert!((policy.entropy_bits() - (810f64 - 16.0).log2()).abs() < 1e-9);
+    }
+    #[test]
+    fn pronounceable() {
+        let policy = PronounceablePolicy {
+            length: 6,
+            capitalize: true,
+            digits: 2,
+        };
+        let generated = policy.generate().unwrap();
+        let password: Vec<char> = generated.password.expose_secret().chars().collect();
+        assert_eq!(password.len(), 8);
+        assert!(password[0].is_ascii_uppercase());
+        assert!(PRONOUNCEABLE_VOWELS.contains(password[1]));
+        assert!(PRONOUNCEABLE_CONSONANTS.contains(password[2]));
+        assert!(password[6..].iter().all(|c| c.is_ascii_digit()));
+        let expected = 3.0 * 16f64.log2() + 3.0 * 5f64.log2() + 2.0 * 10f64.log2();
+        assert!((generated.entropy_bits - expected).abs() < 1e-9);
+    }
+}
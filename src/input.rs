@@ -0,0 +1,183 @@
+// reading secrets (like the master password) without putting them on the command line,
+// where they'd end up in shell history and /proc/<pid>/cmdline
+
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read};
+use std::path::PathBuf;
+
+use zeroize::Zeroizing;
+
+use crate::secret::SecretString;
+use crate::{Error, Result};
+
+/// Environment variable the master password can be read from, for automation.
+pub const MASTER_PASSWORD_ENV: &str = "PWD_RS_MASTER";
+
+/// Where to read a secret from.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SecretSource {
+    /// A hidden prompt on the terminal
+    Prompt,
+    /// The first line of standard input
+    Stdin,
+    /// The first line read from an already open file descriptor
+    Fd(i32),
+    /// The first line of a file
+    File(PathBuf),
+    /// An environment variable, which is removed once it's been read
+    Env(String),
+    /// Passed directly on the command line (insecure)
+    Argument(String),
+}
+
+/// Reads a secret from `source`. `prompt` is only shown for `SecretSource::Prompt`.
+pub fn read_secret(source: &SecretSource, prompt: &str) -> Result<SecretString> {
+    match source {
+        SecretSource::Prompt => rpassword::prompt_password(prompt)
+            .map(SecretString::new)
+            .map_err(|e| {
+                Error::Config(format!(
+                    "could not prompt for a password ({}), use --password-stdin, --password-file or {} instead",
+                    e, MASTER_PASSWORD_ENV
+                ))
+            }),
+        SecretSource::Stdin => read_first_line(std::io::stdin().lock()),
+        SecretSource::Fd(fd) => read_first_line(open_fd(*fd)?),
+        SecretSource::File(path) => read_first_line(File::open(path)?),
+        SecretSource::Env(var) => {
+            let secret =
+                std::env::var(var).map_err(|_| Error::Config(format!("{} is not set", var)))?;
+            // so it isn't passed on to anything we start
+            std::env::remove_var(var);
+            Ok(SecretString::new(secret))
+        }
+        SecretSource::Argument(secret) => Ok(SecretString::from(secret.as_str())),
+    }
+}
+
+/// Prompts for a new secret twice, and fails with `Error::InvalidInput` if the two don't match.
+pub fn prompt_new_secret(prompt: &str, confirm_prompt: &str) -> Result<SecretString> {
+    let secret = SecretString::new(rpassword::prompt_password(prompt)?);
+    let confirmation = SecretString::new(rpassword::prompt_password(confirm_prompt)?);
+    if confirmation != secret {
+        return Err(Error::InvalidInput("passwords don't match".to_string()));
+    }
+    Ok(secret)
+}
+
+// reads a single line, without its line ending. an empty input is an error,
+// since an empty master password is never what anyone meant
+fn read_first_line(reader: impl Read) -> Result<SecretString> {
+    let mut line = Zeroizing::new(String::new());
+    BufReader::new(reader).read_line(&mut line)?;
+    let secret = line.strip_suffix('\n').unwrap_or(&line);
+    let secret = secret.strip_suffix('\r').unwrap_or(secret);
+    if secret.is_empty() {
+        return Err(Error::InvalidInput("no password was given".to_string()));
+    }
+    Ok(SecretString::from(secret))
+}
+
+// the descriptor is duplicated rather than taken over, so whatever it is, closing our copy
+// once the password has been read can't close a descriptor something else in the program is using
+#[cfg(unix)]
+fn open_fd(fd: i32) -> Result<File> {
+    use std::os::fd::BorrowedFd;
+    // stdin, stdout and stderr belong to the rest of the program
+    if fd < 3 {
+        return Err(Error::InvalidInput(format!(
+            "file descriptor {} can't be used, use --password-stdin to read from stdin",
+            fd
+        )));
+    }
+    // SAFETY: F_GETFD only reads the descriptor's flags, and fails (with EBADF) if it isn't open
+    if unsafe { libc::fcntl(fd, libc::F_GETFD) } == -1 {
+        return Err(Error::InvalidInput(format!(
+            "file descriptor {} isn't open",
+            fd
+        )));
+    }
+    // SAFETY: the descriptor is open (checked above), and only borrowed for as long as it takes to duplicate it
+    let borrowed = unsafe { BorrowedFd::borrow_raw(fd) };
+    Ok(File::from(borrowed.try_clone_to_owned()?))
+}
+#[cfg(not(unix))]
+fn open_fd(_fd: i32) -> Result<File> {
+    Err(Error::Config(
+        "reading from a file descriptor is only supported on unix".to_string(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{read_first_line, read_secret, SecretSource};
+
+    #[test]
+    fn first_line() {
+        assert_eq!(
+            read_first_line(&b"hunter2\n"[..]).unwrap().expose_secret(),
+            "hunter2"
+        );
+        assert_eq!(
+            read_first_line(&b"hunter2\r\nmore"[..])
+                .unwrap()
+                .expose_secret(),
+            "hunter2"
+        );
+        assert_eq!(
+            read_first_line(&b"hunter2"[..]).unwrap().expose_secret(),
+            "hunter2"
+        );
+        // surrounding spaces are part of the password
+        assert_eq!(
+            read_first_line(&b" hunter2 \n"[..])
+                .unwrap()
+                .expose_secret(),
+            " hunter2 "
+        );
+        assert!(matches!(
+            read_first_line(&b"\n"[..]),
+            Err(crate::Error::InvalidInput(_))
+        ));
+    }
+    #[test]
+    fn file() {
+        let path = std::env::temp_dir().join(format!("pwd-rs-input-test-{}", std::process::id()));
+        std::fs::write(&path, "hunter2\n").unwrap();
+        let secret = read_secret(&SecretSource::File(path.clone()), "").unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(secret.expose_secret(), "hunter2");
+    }
+    #[cfg(unix)]
+    #[test]
+    fn fd() {
+        use std::os::fd::AsRawFd;
+        let path = std::env::temp_dir().join(format!("pwd-rs-fd-test-{}", std::process::id()));
+        std::fs::write(&path, "hunter2\n").unwrap();
+        let file = std::fs::File::open(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let source = SecretSource::Fd(file.as_raw_fd());
+        assert_eq!(read_secret(&source, "").unwrap().expose_secret(), "hunter2");
+        // the descriptor is still open afterwards, since only a duplicate of it was closed
+        assert!(file.metadata().is_ok());
+        for fd in [1, 1000] {
+            assert!(matches!(
+                read_secret(&SecretSource::Fd(fd), ""),
+                Err(crate::Error::InvalidInput(_))
+            ));
+        }
+    }
+    #[test]
+    fn env() {
+        let var = "PWD_RS_MASTER_INPUT_TEST";
+        std::env::set_var(var, "hunter2");
+        let source = SecretSource::Env(var.to_string());
+        assert_eq!(read_secret(&source, "").unwrap().expose_secret(), "hunter2");
+        // it's only readable once
+        assert!(std::env::var(var).is_err());
+        assert!(matches!(
+            read_secret(&source, ""),
+            Err(crate::Error::Config(_))
+        ));
+    }
+}
@@ -0,0 +1,262 @@
+// vault-level key derivation settings
+// every vault stores one of these headers in the metadata table, and the key used to encrypt records
+// is derived from the master password with it, instead of using each record's name as a salt.
+
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use argon2::{Algorithm, Argon2, Params, Version};
+use pbkdf2::pbkdf2_hmac;
+use rand::{rngs::OsRng, RngCore};
+use sha2::Sha256;
+
+use crate::secret::Key;
+use crate::{Error, Result};
+
+// length in bytes of the random salt stored in each header
+const SALT_LENGTH: usize = 16;
+
+/// The key derivation function (and its parameters) used by a vault.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Kdf {
+    /// PBKDF2-HMAC-SHA256, kept around for vaults created before Argon2id was supported
+    Pbkdf2 { iterations: u32 },
+    /// Argon2id, `memory` is in KiB
+    Argon2id {
+        memory: u32,
+        time: u32,
+        parallelism: u32,
+    },
+}
+
+impl Kdf {
+    /// the default for new vaults
+    pub const DEFAULT_ARGON2ID: Kdf = Kdf::Argon2id {
+        memory: Params::DEFAULT_M_COST,
+        time: Params::DEFAULT_T_COST,
+        parallelism: Params::DEFAULT_P_COST,
+    };
+    /// the default for vaults that were created before KDF headers existed
+    pub const DEFAULT_PBKDF2: Kdf = Kdf::Pbkdf2 {
+        iterations: 600_000,
+    };
+
+    /// Derives a 256-bit key from `master_password` and `salt`.
+    /// Fails with `Error::Config` if the parameters (e.g. from a vault's header) aren't valid.
+    pub fn derive_key(&self, master_password: impl AsRef<[u8]>, salt: &[u8]) -> Result<Key> {
+        // derived straight into the key's own (locked) allocation
+        let mut key = Key::zeroed();
+        let derived_key = key.as_mut_bytes();
+        match *self {
+            Kdf::Pbkdf2 { iterations } => {
+                pbkdf2_hmac::<Sha256>(master_password.as_ref(), salt, iterations, derived_key);
+            }
+            Kdf::Argon2id {
+                memory,
+                time,
+                parallelism,
+            } => {
+                let params = Params::new(memory, time, parallelism, Some(derived_key.len()))
+                    .map_err(|e| Error::Config(format!("invalid argon2 parameters: {}", e)))?;
+                Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+                    .hash_password_into(master_password.as_ref(), salt, derived_key)
+                    .map_err(|e| Error::Config(format!("error deriving key: {}", e)))?;
+            }
+        }
+        Ok(key)
+    }
+    // times a single key derivation with a throwaway password and salt
+    fn time(&self) -> Result<Duration> {
+        let start = Instant::now();
+        self.derive_key("benchmark", &[0u8; SALT_LENGTH])?;
+        Ok(start.elapsed())
+    }
+}
+
+impl fmt::Display for Kdf {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Kdf::Pbkdf2 { iterations } => write!(f, "pbkdf2-sha256 (iterations={})", iterations),
+            Kdf::Argon2id {
+                memory,
+                time,
+                parallelism,
+            } => write!(
+                f,
+                "argon2id (memory={} KiB, time={}, parallelism={})",
+                memory, time, parallelism
+            ),
+        }
+    }
+}
+
+/// A KDF together with the vault's random salt, as stored in the metadata table.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct KdfHeader {
+    pub kdf: Kdf,
+    pub salt: Vec<u8>,
+}
+
+impl KdfHeader {
+    /// Creates a header for `kdf` with a new random salt.
+    pub fn new(kdf: Kdf) -> Self {
+        let mut salt = vec![0u8; SALT_LENGTH];
+        OsRng.fill_bytes(&mut salt);
+        KdfHeader { kdf, salt }
+    }
+    /// Derives the vault key from `master_password`.
+    pub fn derive_key(&self, master_password: impl AsRef<[u8]>) -> Result<Key> {
+        self.kdf.derive_key(master_password, &self.salt)
+    }
+    /// Parses a header written by `to_string()`, e.g. `argon2id$m=19456,t=2,p=1$<hex salt>`.
+    /// Returns `None` if the header is malformed.
+    pub fn parse(header: &str) -> Option<Self> {
+        let mut parts = header.split('$');
+        let (algorithm, params, salt) = (parts.next()?, parts.next()?, parts.next()?);
+        if parts.next().is_some() {
+            return None;
+        }
+        // params look like "m=19456,t=2,p=1"
+        let param = |key: &str| -> Option<u32> {
+            params
+                .split(',')
+                .find_map(|p| p.strip_prefix(key)?.strip_prefix('='))?
+                .parse()
+                .ok()
+        };
+        let kdf = match algorithm {
+            "pbkdf2-sha256" => Kdf::Pbkdf2 {
+                iterations: param("i")?,
+            },
+            "argon2id" => Kdf::Argon2id {
+                memory: param("m")?,
+                time: param("t")?,
+                parallelism: param("p")?,
+            },
+            _ => return None,
+        };
+        Some(KdfHeader {
+            kdf,
+            salt: hex::decode(salt).ok()?,
+        })
+    }
+}
+
+impl fmt::Display for KdfHeader {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let salt = hex::encode(&self.salt);
+        match self.kdf {
+            Kdf::Pbkdf2 { iterations } => write!(f, "pbkdf2-sha256$i={}${}", iterations, salt),
+            Kdf::Argon2id {
+                memory,
+                time,
+                parallelism,
+            } => write!(
+                f,
+                "argon2id$m={},t={},p={}${}",
+                memory, time, parallelism, salt
+            ),
+        }
+    }
+}
+
+// upper bound for tuning argon2id memory, 1 GiB in KiB
+const MAX_TUNED_MEMORY: u32 = 1024 * 1024;
+
+/// Benchmarks this machine and picks parameters so that one key derivation takes roughly `target`.
+/// For Argon2id, memory is doubled first (up to 1 GiB), then extra passes are added.
+/// For PBKDF2, the iteration count is scaled from a short measurement.
+pub fn tune(kdf: Kdf, target: Duration) -> Result<Kdf> {
+    Ok(match kdf {
+        Kdf::Pbkdf2 { .. } => {
+            let sample = 100_000;
+            let elapsed = Kdf::Pbkdf2 { iterations: sample }.time()?;
+            let scale = target.as_secs_f64() / elapsed.as_secs_f64().max(f64::EPSILON);
+            Kdf::Pbkdf2 {
+                iterations: ((sample as f64 * scale) as u32).max(sample),
+            }
+        }
+        Kdf::Argon2id { parallelism, .. } => {
+            let mut memory = Params::DEFAULT_M_COST;
+            let mut time = Params::DEFAULT_T_COST;
+            let candidate = |memory, time| Kdf::Argon2id {
+                memory,
+                time,
+                parallelism,
+            };
+            // memory hardness is the point of argon2, so spend the budget on memory first
+            while memory * 2 <= MAX_TUNED_MEMORY && candidate(memory * 2, time).time()? <= target {
+                memory *= 2;
+            }
+            while time < 10 && candidate(memory, time + 1).time()? <= target {
+                time += 1;
+            }
+            candidate(memory, time)
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Kdf, KdfHeader};
+
+    #[test]
+    fn header_round_trip() {
+        for kdf in [Kdf::DEFAULT_ARGON2ID, Kdf::DEFAULT_PBKDF2] {
+            let header = KdfHeader::new(kdf);
+            let parsed = KdfHeader::parse(&header.to_string()).unwrap();
+            assert_eq!(parsed, header);
+        }
+    }
+    #[test]
+    fn malformed_headers() {
+        assert!(KdfHeader::parse("").is_none());
+        assert!(KdfHeader::parse("scrypt$n=1$00").is_none());
+        assert!(KdfHeader::parse("argon2id$m=1,t=2$00").is_none());
+        assert!(KdfHeader::parse("pbkdf2-sha256$i=1$zz").is_none());
+    }
+    #[test]
+    fn random_salts() {
+        assert_ne!(
+            KdfHeader::new(Kdf::DEFAULT_ARGON2ID).salt,
+            KdfHeader::new(Kdf::DEFAULT_ARGON2ID).salt
+        );
+    }
+    #[test]
+    fn pbkdf2_matches_reference() {
+        // same literal as the crypto.rs tests, 4096 iterations of pbkdf2-hmac-sha256
+        let key = Kdf::Pbkdf2 { iterations: 4096 }
+            .derive_key("mymasterpassword", b"salt")
+            .unwrap();
+        assert_eq!(
+            hex::encode(key.as_bytes()),
+            "8f21affeb61e304e7b474229ffeb34309ed31beda58d153bc7ad9da6e9b6184c"
+        );
+    }
+    #[test]
+    fn argon2id_depends_on_salt() {
+        let header = KdfHeader::new(Kdf::DEFAULT_ARGON2ID);
+        let other = KdfHeader::new(Kdf::DEFAULT_ARGON2ID);
+        assert_eq!(
+            header.derive_key("mymasterpassword").unwrap(),
+            header.derive_key("mymasterpassword").unwrap()
+        );
+        assert_ne!(
+            header.derive_key("mymasterpassword").unwrap(),
+            other.derive_key("mymasterpassword").unwrap()
+        );
+    }
+    #[test]
+    fn invalid_argon2_parameters() {
+        // a header with parameters argon2 doesn't accept shouldn't panic
+        let kdf = Kdf::Argon2id {
+            memory: 1,
+            time: 0,
+            parallelism: 1,
+        };
+        assert!(matches!(
+            kdf.derive_key("mymasterpassword", &[0u8; 16]),
+            Err(crate::Error::Config(_))
+        ));
+    }
+}
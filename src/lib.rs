@@ -1,6 +1,17 @@
+pub mod agent;
 pub mod args;
+pub mod clipboard;
 pub mod console;
 pub mod crypto;
+pub mod error;
+pub mod generator;
+pub mod input;
+pub mod kdf;
 pub mod models;
 pub mod ops;
 pub mod schema;
+pub mod secret;
+pub mod strength;
+pub mod vault;
+
+pub use error::{Error, Result};
@@ -1,5 +1,6 @@
-use crate::schema::password;
+use crate::schema::{metadata, password};
 use diesel::prelude::*;
+use zeroize::Zeroize;
 // this is the main struct that provides the table and columns
 // suitable for selects and queries, made evident by the derivations
 #[derive(Queryable, Selectable)]
@@ -13,6 +14,20 @@ pub struct Password {
     pub pass: Option<String>,
     pub notes: Option<String>,
     pub aes_nonce: String,
+    pub version: i32,
+    pub name_index: String,
+}
+
+// records returned by ops::read_and_decrypt() hold plaintext, so every record is wiped when it's dropped.
+// (this means fields have to be taken out with std::mem::take() or Option::take() rather than moved)
+impl Drop for Password {
+    fn drop(&mut self) {
+        self.name.zeroize();
+        self.username.zeroize();
+        self.email.zeroize();
+        self.pass.zeroize();
+        self.notes.zeroize();
+    }
 }
 
 // struct to insert a new password
@@ -26,6 +41,8 @@ pub struct NewPassword<'a> {
     pub pass: Option<&'a str>,
     pub notes: Option<&'a str>,
     pub aes_nonce: &'a str,
+    pub version: i32,
+    pub name_index: &'a str,
 }
 // struct to update passwords
 // all fields are optional,
@@ -39,5 +56,14 @@ pub struct PasswordForm<'a> {
     pub email: Option<&'a str>,
     pub pass: Option<&'a str>,
     pub notes: Option<&'a str>,
-    pub aes_nonce: &'a str,
+    pub name_index: Option<&'a str>,
+}
+
+// a single vault setting from the metadata table
+#[derive(Queryable, Selectable, Insertable)]
+#[diesel(table_name = metadata)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct Metadata {
+    pub key: String,
+    pub value: String,
 }
@@ -1,17 +1,56 @@
 // spaghetti code below
 
-use crate::crypto::{decrypt, encrypt, hash};
+use crate::crypto::{
+    associated_data, blind_index, constant_time_eq, decrypt, decrypt_legacy, derive_legacy_key,
+    encrypt, generate_key, hash, is_legacy_verifier, key_check, normalize_name, unwrap_key,
+    verify_master_password, wrap_key,
+};
+use crate::kdf::{Kdf, KdfHeader};
 use crate::models::{NewPassword, Password, PasswordForm};
+use crate::schema::metadata;
 use crate::schema::password::dsl::*;
-use aes_gcm::aead::OsRng;
-use aes_gcm::{AeadCore, Aes256Gcm};
+use crate::secret::{Key, SecretString};
+use crate::{Error, Result};
 use diesel::prelude::*;
 use diesel::sqlite::SqliteConnection;
+use diesel_migrations::{embed_migrations, EmbeddedMigrations, MigrationHarness};
 use dotenvy::dotenv;
 use std::env;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-// this is a constant for the name column of the master record.
-pub const MASTER_KEYWORD: &str = ".master";
+// this is the record format written by this version of pwd-rs.
+// version 0 records share a single nonce across every field, version 1 records store a nonce per field,
+// both of those use a key derived from the record's name. version 2 records use a key derived with the vault's KDF header,
+// version 3 records use the vault's random data key (which is wrapped by that derived key instead),
+// version 4 records also authenticate their id, column and version as associated data,
+// and version 5 records encrypt the name too, and are looked up by name_index instead.
+pub const RECORD_VERSION: i32 = 5;
+// metadata key for the vault's KDF header
+pub const KDF_METADATA_KEY: &str = "kdf";
+// metadata key for the vault's data key, wrapped by the key encryption key derived from the master password
+pub const WRAPPED_KEY_METADATA_KEY: &str = "wrapped_key";
+// metadata key for the data key's check value, see crypto::key_check()
+pub const KEY_CHECK_METADATA_KEY: &str = "key_check";
+// metadata key for the master password verifier of vaults from before data keys existed.
+// newer vaults don't have one, the master password is checked by unwrapping the data key instead
+pub const VERIFIER_METADATA_KEY: &str = "verifier";
+// metadata keys for the number of failed unlocks since the last successful one,
+// and when the last one happened (in seconds since the unix epoch)
+pub const FAILED_UNLOCKS_METADATA_KEY: &str = "failed_unlocks";
+pub const LAST_FAILED_UNLOCK_METADATA_KEY: &str = "last_failed_unlock";
+// failed unlocks allowed before authenticate() starts making you wait, so a typo doesn't cost anything
+pub const FREE_UNLOCK_ATTEMPTS: u32 = 3;
+// the longest authenticate() will make you wait between attempts
+pub const MAX_UNLOCK_DELAY: Duration = Duration::from_secs(15 * 60);
+// metadata key for the schema version the vault was last opened with
+pub const SCHEMA_VERSION_METADATA_KEY: &str = "schema_version";
+// the schema version this version of pwd-rs writes, bump this whenever a migration is added.
+// vaults with a newer schema version are refused, since they may have been changed in ways we can't read.
+pub const SCHEMA_VERSION: i32 = 5;
+
+// the embed_migrations! macro will generate a constant value containing migrations, which are
+// stored in the binary, so a new database can be created without the diesel CLI
+pub const MIGRATIONS: EmbeddedMigrations = embed_migrations!();
 
 // these functions provide the basic CRUD operations, i.e create, read, update, delete
 // currently these functions are not generic, possible todo
@@ -19,202 +58,692 @@ pub const MASTER_KEYWORD: &str = ".master";
 // these functions have a connection parameter so that a ":memory:" connection can be based for in-memory testing
 
 // simple function, returns SqliteConnection by reading address from .env
-pub fn establish_connection() -> Result<SqliteConnection, ConnectionError> {
+// the database is created (and migrated) if it doesn't exist yet
+pub fn establish_connection() -> Result<SqliteConnection> {
+    let mut connection = SqliteConnection::establish(&database_url()?)?;
+    migrate(&mut connection)?;
+    Ok(connection)
+}
+// brings the database up to date with the embedded migrations, and records SCHEMA_VERSION in the metadata table.
+// fails with Error::Config if the vault was written by a newer version of pwd-rs, without touching it.
+pub fn migrate(connection: &mut SqliteConnection) -> Result<()> {
+    // the metadata table is created by a migration itself, so it won't exist in a brand new database
+    let has_metadata = diesel::select(diesel::dsl::sql::<diesel::sql_types::Bool>(
+        "EXISTS (SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'metadata')",
+    ))
+    .get_result::<bool>(connection)?;
+    if has_metadata {
+        if let Some(stored_version) = get_metadata(connection, SCHEMA_VERSION_METADATA_KEY)? {
+            let stored_version: i32 = stored_version
+                .parse()
+                .map_err(|_| Error::Encoding("malformed schema version".to_string()))?;
+            if stored_version > SCHEMA_VERSION {
+                return Err(Error::Config(format!(
+                    "this vault has schema version {}, but this version of pwd-rs only supports up to {}",
+                    stored_version, SCHEMA_VERSION
+                )));
+            }
+        }
+    }
+    connection
+        .run_pending_migrations(MIGRATIONS)
+        .map_err(Error::Database)?;
+    set_metadata(
+        connection,
+        SCHEMA_VERSION_METADATA_KEY,
+        &SCHEMA_VERSION.to_string(),
+    )?;
+    Ok(())
+}
+// reads the database address from .env
+pub fn database_url() -> Result<String> {
     dotenv().ok();
-    let database_url = env::var("DATABASE_URL").expect("DATABASE_URL must be set");
-    SqliteConnection::establish(&database_url)
+    env::var("DATABASE_URL").map_err(|_| Error::Config("DATABASE_URL must be set".to_string()))
+}
+// writes a consistent copy of the whole database to `destination`, which must not exist yet.
+// VACUUM INTO is used rather than copying the file, so it's safe with an open connection.
+pub fn backup_database(connection: &mut SqliteConnection, destination: &str) -> Result<usize> {
+    Ok(diesel::sql_query("VACUUM INTO ?")
+        .bind::<diesel::sql_types::Text, _>(destination)
+        .execute(connection)?)
 }
 // insert password given an object of type NewPassword and a connection
 pub fn insert_password(
     connection: &mut SqliteConnection,
     new_password: NewPassword,
-) -> Result<usize, diesel::result::Error> {
-    diesel::insert_into(password)
+) -> Result<usize> {
+    Ok(diesel::insert_into(password)
         .values(&new_password)
-        .execute(connection)
+        .execute(connection)?)
 }
 
-// get a password given a name of type &str and a connection
-pub fn get_password(
-    connection: &mut SqliteConnection,
-    term: &str,
-) -> Result<Option<Password>, diesel::result::Error> {
-    password
-        .filter(name.eq(term))
+// the next few functions look records up by name_index, which is the blind index of the name (see crypto::blind_index()).
+// names themselves are encrypted, so they can't be searched.
+
+// get a password given its name index and a connection
+pub fn get_password(connection: &mut SqliteConnection, term: &str) -> Result<Option<Password>> {
+    Ok(password
+        .filter(name_index.eq(term))
         .select(Password::as_select())
         .first(connection)
-        .optional()
+        .optional()?)
 }
-// delete a password given a name, again, not generic.
-pub fn delete_password(
-    connection: &mut SqliteConnection,
-    term: &str,
-) -> Result<usize, diesel::result::Error> {
-    diesel::delete(password.filter(name.eq(term))).execute(connection)
+// delete a password given its name index, again, not generic.
+pub fn delete_password(connection: &mut SqliteConnection, term: &str) -> Result<usize> {
+    Ok(diesel::delete(password.filter(name_index.eq(term))).execute(connection)?)
 }
 
 pub fn update_password(
     connection: &mut SqliteConnection,
     term: &str,
     form: PasswordForm,
-) -> Result<usize, diesel::result::Error> {
-    diesel::update(password.filter(name.eq(term)))
+) -> Result<usize> {
+    Ok(diesel::update(password.filter(name_index.eq(term)))
         .set(form)
-        .execute(connection)
+        .execute(connection)?)
+}
+
+pub fn check_password_exists(connection: &mut SqliteConnection, term: &str) -> Result<bool> {
+    Ok(get_password(connection, term)?.is_some())
 }
 
-pub fn check_password_exists(
+// get a vault setting from the metadata table
+pub fn get_metadata(
     connection: &mut SqliteConnection,
-    term: &str,
-) -> Result<bool, diesel::result::Error> {
-    let result = get_password(connection, term);
-    match result {
-        Ok(values) => match values {
-            Some(_) => Ok(true),
-            None => Ok(false),
-        },
-        Err(e) => Err(e),
-    }
+    metadata_key: &str,
+) -> Result<Option<String>> {
+    Ok(metadata::table
+        .find(metadata_key)
+        .select(metadata::value)
+        .first(connection)
+        .optional()?)
+}
+// remove a vault setting, if it's there
+pub fn delete_metadata(connection: &mut SqliteConnection, metadata_key: &str) -> Result<usize> {
+    Ok(diesel::delete(metadata::table.find(metadata_key)).execute(connection)?)
+}
+// set a vault setting, overwriting it if it already exists
+pub fn set_metadata(
+    connection: &mut SqliteConnection,
+    metadata_key: &str,
+    metadata_value: &str,
+) -> Result<usize> {
+    Ok(diesel::replace_into(metadata::table)
+        .values((
+            metadata::key.eq(metadata_key),
+            metadata::value.eq(metadata_value),
+        ))
+        .execute(connection)?)
+}
+pub fn get_kdf_header(connection: &mut SqliteConnection) -> Result<Option<KdfHeader>> {
+    get_metadata(connection, KDF_METADATA_KEY)?
+        .map(|header| {
+            KdfHeader::parse(&header).ok_or(Error::Encoding("malformed KDF header".to_string()))
+        })
+        .transpose()
+}
+// unlocks the vault: derives the key encryption key from the master password with the vault's KDF header,
+// then unwraps the data key that records are encrypted with. this is the only KDF run a session needs.
+// vaults from before data keys existed won't have one until upgrade_records() has run.
+pub fn unlock_vault_key(connection: &mut SqliteConnection, master_password: &str) -> Result<Key> {
+    let header = get_kdf_header(connection)?.ok_or(Error::NotFound("KDF header".to_string()))?;
+    let wrapped_key = get_metadata(connection, WRAPPED_KEY_METADATA_KEY)?
+        .ok_or(Error::NotFound("vault key".to_string()))?;
+    let kek = header.derive_key(master_password)?;
+    unwrap_key(&kek, &wrapped_key)
+}
+
+// checks a data key that didn't come from unlock_vault_key() (e.g. one from the agent) against the vault's check value.
+// returns false if it's the wrong key, or the vault doesn't have a check value yet (upgrade_records() adds one).
+pub fn check_vault_key(connection: &mut SqliteConnection, key: &Key) -> Result<bool> {
+    Ok(get_metadata(connection, KEY_CHECK_METADATA_KEY)?
+        .is_some_and(|check| constant_time_eq(check, key_check(key))))
 }
 
-pub fn authenticate(
+// returns true once initialize_vault() has set a master password
+pub fn is_initialized(connection: &mut SqliteConnection) -> Result<bool> {
+    Ok(
+        get_metadata(connection, WRAPPED_KEY_METADATA_KEY)?.is_some()
+            || get_metadata(connection, VERIFIER_METADATA_KEY)?.is_some(),
+    )
+}
+// checks the master password, see authenticate_and_unlock()
+pub fn authenticate(connection: &mut SqliteConnection, master_password: &[u8]) -> Result<bool> {
+    Ok(authenticate_and_unlock(connection, master_password)?.is_some())
+}
+// checks the master password, and returns the vault's data key if it's right or None if it isn't.
+// the password is checked by unwrapping the data key with the key derived from it, so a guess costs
+// exactly one run of the vault's KDF, and the key doesn't have to be derived again to unlock the vault.
+// failures are counted in the metadata table, and once there have been more than FREE_UNLOCK_ATTEMPTS in a row,
+// this waits (see unlock_delay()) before checking the password at all. a successful check resets the count,
+// but the time of the last failure is kept so it can still be shown by `pwd-rs status`.
+pub fn authenticate_and_unlock(
     connection: &mut SqliteConnection,
     master_password: &[u8],
-) -> Result<bool, diesel::result::Error> {
-    let res = get_password(connection, MASTER_KEYWORD);
-    match res {
-        Ok(v) => match v {
-            Some(value) => Ok(hex::decode(
-                value
-                    .pass
-                    .expect("Error: Master record exists but has no password"),
-            )
-            .expect("Error decoding")
-                == hash(master_password).to_vec()),
-            None => Err(diesel::result::Error::NotFound),
-        },
-        Err(e) => Err(e),
+) -> Result<Option<Key>> {
+    std::thread::sleep(remaining_unlock_delay(connection)?);
+    let data_key = match get_metadata(connection, WRAPPED_KEY_METADATA_KEY)? {
+        Some(wrapped_key) => {
+            let header =
+                get_kdf_header(connection)?.ok_or(Error::NotFound("KDF header".to_string()))?;
+            match unwrap_key(&header.derive_key(master_password)?, &wrapped_key) {
+                Ok(data_key) => Some(data_key),
+                Err(Error::Decryption) => None,
+                Err(e) => return Err(e),
+            }
+        }
+        None => check_verifier(connection, master_password)?,
+    };
+    match &data_key {
+        Some(data_key) => connection.transaction(|connection| {
+            set_metadata(connection, FAILED_UNLOCKS_METADATA_KEY, "0")?;
+            // vaults that got a data key before verifiers were dropped still have one, which is cheaper to attack
+            delete_metadata(connection, VERIFIER_METADATA_KEY)?;
+            if get_metadata(connection, KEY_CHECK_METADATA_KEY)?.is_none() {
+                set_metadata(connection, KEY_CHECK_METADATA_KEY, &key_check(data_key))?;
+            }
+            Ok::<_, Error>(())
+        })?,
+        None => record_failed_unlock(connection)?,
     }
+    Ok(data_key)
 }
-pub fn insert_master_password(
+// vaults from before data keys existed are checked against their verifier: an Argon2id one,
+// or an unsalted SHA-256 hash in even older vaults. if the password matches, the vault gets a data key
+// wrapped by its KDF (PBKDF2 if it doesn't have a header yet), which replaces the verifier from then on.
+fn check_verifier(
     connection: &mut SqliteConnection,
-    data: &[u8],
-) -> Result<usize, diesel::result::Error> {
-    let master_password = hash(data);
-    let encoded = hex::encode(master_password);
-    let data = Some(encoded.as_str());
-    diesel::insert_into(password)
-        .values(NewPassword {
-            name: MASTER_KEYWORD,
-            username: None,
-            email: None,
-            pass: data,
-            notes: None,
-            aes_nonce: "",
-        })
-        .execute(connection)
+    master_password: &[u8],
+) -> Result<Option<Key>> {
+    let verifier = get_metadata(connection, VERIFIER_METADATA_KEY)?
+        .ok_or(Error::NotFound("master password".to_string()))?;
+    let matches = if is_legacy_verifier(&verifier) {
+        // compared in constant time, so the time taken doesn't leak how much of the hash matched
+        constant_time_eq(hex::decode(verifier)?, hash(master_password))
+    } else {
+        verify_master_password(master_password, &verifier)?
+    };
+    if !matches {
+        return Ok(None);
+    }
+    let header = match get_kdf_header(connection)? {
+        Some(header) => header,
+        None => KdfHeader::new(Kdf::DEFAULT_PBKDF2),
+    };
+    let data_key = generate_key();
+    let wrapped_key = wrap_key(&header.derive_key(master_password)?, &data_key)?;
+    connection.transaction(|connection| {
+        set_metadata(connection, KDF_METADATA_KEY, &header.to_string())?;
+        set_metadata(connection, WRAPPED_KEY_METADATA_KEY, &wrapped_key)?;
+        Ok::<_, Error>(())
+    })?;
+    Ok(Some(data_key))
+}
+// returns the number of failed unlocks since the last successful one,
+// and when the last failed unlock happened (even if there's been a successful one since)
+pub fn get_failed_unlocks(connection: &mut SqliteConnection) -> Result<(u32, Option<SystemTime>)> {
+    let parse = |value: Option<String>| -> Result<Option<u64>> {
+        value
+            .map(|value| {
+                value
+                    .parse()
+                    .map_err(|_| Error::Encoding("malformed failed unlock counter".to_string()))
+            })
+            .transpose()
+    };
+    let failures = parse(get_metadata(connection, FAILED_UNLOCKS_METADATA_KEY)?)?.unwrap_or(0);
+    let last_failure = parse(get_metadata(connection, LAST_FAILED_UNLOCK_METADATA_KEY)?)?
+        .map(|secs| UNIX_EPOCH + Duration::from_secs(secs));
+    Ok((u32::try_from(failures).unwrap_or(u32::MAX), last_failure))
+}
+// counts a failed unlock, and records when it happened
+pub fn record_failed_unlock(connection: &mut SqliteConnection) -> Result<()> {
+    let (failures, _) = get_failed_unlocks(connection)?;
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|t| t.as_secs())
+        .unwrap_or_default();
+    connection.transaction(|connection| {
+        set_metadata(
+            connection,
+            FAILED_UNLOCKS_METADATA_KEY,
+            &failures.saturating_add(1).to_string(),
+        )?;
+        set_metadata(
+            connection,
+            LAST_FAILED_UNLOCK_METADATA_KEY,
+            &now.to_string(),
+        )?;
+        Ok(())
+    })
+}
+// how long to wait after the last failed unlock before trying again, given how many failures there have been in a row.
+// the first FREE_UNLOCK_ATTEMPTS are free, after that the delay starts at 1 second and doubles with every failure.
+pub fn unlock_delay(failures: u32) -> Duration {
+    if failures < FREE_UNLOCK_ATTEMPTS {
+        return Duration::ZERO;
+    }
+    let exponent = (failures - FREE_UNLOCK_ATTEMPTS).min(31);
+    Duration::from_secs(1u64 << exponent).min(MAX_UNLOCK_DELAY)
+}
+// how much of the current delay is left, which is zero once enough time has passed since the last failure.
+// the delay is based on the stored timestamp, so starting pwd-rs again doesn't skip it.
+pub fn remaining_unlock_delay(connection: &mut SqliteConnection) -> Result<Duration> {
+    let (failures, last_failure) = get_failed_unlocks(connection)?;
+    let Some(last_failure) = last_failure else {
+        return Ok(Duration::ZERO);
+    };
+    // if the clock has gone backwards, the full delay applies
+    let elapsed = SystemTime::now()
+        .duration_since(last_failure)
+        .unwrap_or_default();
+    Ok(unlock_delay(failures).saturating_sub(elapsed))
+}
+// sets up a new vault: a KDF header for `kdf` and a random data key, wrapped by the key derived from the master password.
+// fails with Error::AlreadyExists if the vault already has a master password.
+pub fn initialize_vault(connection: &mut SqliteConnection, data: &[u8], kdf: Kdf) -> Result<()> {
+    if is_initialized(connection)? {
+        return Err(Error::AlreadyExists("vault".to_string()));
+    }
+    let header = KdfHeader::new(kdf);
+    let data_key = generate_key();
+    let wrapped_key = wrap_key(&header.derive_key(data)?, &data_key)?;
+    connection.transaction(|connection| {
+        set_metadata(connection, KDF_METADATA_KEY, &header.to_string())?;
+        set_metadata(connection, WRAPPED_KEY_METADATA_KEY, &wrapped_key)?;
+        set_metadata(connection, KEY_CHECK_METADATA_KEY, &key_check(&data_key))?;
+        Ok(())
+    })
 }
 // higher level functions::
 
+// these take the vault's data key from unlock_vault_key() rather than the master password,
+// so the (slow) key derivation only has to happen once
+
 // this function will take in the parameters for a new password entry and encrypt each one, then store the values
+// fails with Error::AlreadyExists if there's already a record with the same (normalized) name
 pub fn encrypt_and_insert(
     connection: &mut SqliteConnection,
-    master_password: &str,
+    key: &Key,
     new_name: &str,
-    new_username: Option<String>,
-    new_email: Option<String>,
-    new_pass: Option<String>,
-    new_notes: Option<String>,
-) -> Result<usize, diesel::result::Error> {
-    let nonce = Aes256Gcm::generate_nonce(OsRng);
-    let encoded_nonce = hex::encode(nonce);
-
-    let encrypted_username = encrypt(master_password, new_username, nonce, new_name);
-    let encrypted_email = encrypt(master_password, new_email, nonce, new_name);
-    let encrypted_password = encrypt(master_password, new_pass, nonce, new_name);
-    let encrypted_notes = encrypt(master_password, new_notes, nonce, new_name);
-
-    let new_password = NewPassword {
-        name: new_name,
-        username: encrypted_username.as_deref(),
-        email: encrypted_email.as_deref(),
-        pass: encrypted_password.as_deref(),
-        notes: encrypted_notes.as_deref(),
-        aes_nonce: &encoded_nonce,
+    new_username: Option<&str>,
+    new_email: Option<&str>,
+    new_pass: Option<&str>,
+    new_notes: Option<&str>,
+) -> Result<usize> {
+    let index = blind_index(key, new_name);
+    connection.transaction(|connection| {
+        // the record's id is part of each field's associated data, so the row has to exist first
+        let new_password = NewPassword {
+            name: "",
+            username: None,
+            email: None,
+            pass: None,
+            notes: None,
+            aes_nonce: "",
+            version: RECORD_VERSION,
+            name_index: &index,
+        };
+        insert_password(connection, new_password).map_err(|e| match e {
+            Error::AlreadyExists(_) => Error::AlreadyExists(format!("password \"{}\"", new_name)),
+            e => e,
+        })?;
+        let record_id = password
+            .filter(name_index.eq(&index))
+            .select(id)
+            .first::<i32>(connection)?;
+
+        // encrypt() generates a new nonce for every field
+        let encrypt_field = |field: &str, data: Option<&str>| {
+            encrypt(
+                key,
+                data,
+                &associated_data(record_id, field, RECORD_VERSION),
+            )
+        };
+        Ok(diesel::update(password.find(record_id))
+            .set((
+                name.eq(encrypt_field("name", Some(new_name))?.unwrap_or_default()),
+                username.eq(encrypt_field("username", new_username)?),
+                email.eq(encrypt_field("email", new_email)?),
+                pass.eq(encrypt_field("pass", new_pass)?),
+                notes.eq(encrypt_field("notes", new_notes)?),
+            ))
+            .execute(connection)?)
+    })
+}
+
+// the name index isn't part of any field's associated data, so rows' indexes can be swapped around in the database
+// without anything failing to decrypt. a row is only the one that was looked up if its decrypted name matches too
+fn check_name(decrypted_name: &str, term: &str) -> Result<()> {
+    if normalize_name(decrypted_name) != normalize_name(term) {
+        return Err(Error::Decryption);
+    }
+    Ok(())
+}
+
+// the id of the record named `term`, or none if there isn't one. see check_name()
+fn find_record_id(connection: &mut SqliteConnection, key: &Key, term: &str) -> Result<Option<i32>> {
+    let Some(record) = get_password(connection, &blind_index(key, term))? else {
+        return Ok(None);
     };
-    insert_password(connection, new_password)
+    let record_name = decrypt(
+        key,
+        Some(&record.name),
+        &associated_data(record.id, "name", record.version),
+    )?
+    .unwrap_or_default();
+    check_name(record_name.expose_secret(), term)?;
+    Ok(Some(record.id))
+}
+
+// deletes the record named `term`, returning the number of records deleted (0 if there's no such record)
+pub fn delete_record(connection: &mut SqliteConnection, key: &Key, term: &str) -> Result<usize> {
+    match find_record_id(connection, key, term)? {
+        Some(record_id) => Ok(diesel::delete(password.find(record_id)).execute(connection)?),
+        None => Ok(0),
+    }
 }
 
 // this function will search by the term parameter for a password, and decrypt the fields if the password is found.
 // if there is no password found, the function returns none.
+// the decrypted record wipes its fields when it's dropped, see models::Password.
 pub fn read_and_decrypt(
     connection: &mut SqliteConnection,
-    master_password: &str,
+    key: &Key,
     term: &str,
-) -> Result<Option<Password>, diesel::result::Error> {
-    let pwd = get_password(connection, term);
-    match pwd {
-        Ok(value) => match value {
-            Some(value) => {
-                let decrypted_username = decrypt(
-                    master_password,
-                    value.username,
-                    &value.aes_nonce,
-                    &value.name,
-                );
-                let decrypted_email =
-                    decrypt(master_password, value.email, &value.aes_nonce, &value.name);
-                let decrypted_pass =
-                    decrypt(master_password, value.pass, &value.aes_nonce, &value.name);
-                let decrypted_notes =
-                    decrypt(master_password, value.notes, &value.aes_nonce, &value.name);
-                Ok(Some(Password {
-                    id: value.id,
-                    name: value.name,
-                    username: decrypted_username,
-                    email: decrypted_email,
-                    pass: decrypted_pass,
-                    notes: decrypted_notes,
-                    aes_nonce: value.aes_nonce,
-                }))
-            }
-            None => Ok(None),
-        },
-        Err(e) => Err(e),
-    }
+) -> Result<Option<Password>> {
+    let Some(mut value) = get_password(connection, &blind_index(key, term))? else {
+        return Ok(None);
+    };
+    let (record_id, record_version) = (value.id, value.version);
+    let decrypt_field = |field: &str, data: Option<String>| -> Result<Option<String>> {
+        Ok(decrypt(
+            key,
+            data,
+            &associated_data(record_id, field, record_version),
+        )?
+        .map(SecretString::into_inner))
+    };
+    value.name = decrypt_field("name", Some(std::mem::take(&mut value.name)))?.unwrap_or_default();
+    check_name(&value.name, term)?;
+    value.username = decrypt_field("username", value.username.take())?;
+    value.email = decrypt_field("email", value.email.take())?;
+    value.pass = decrypt_field("pass", value.pass.take())?;
+    value.notes = decrypt_field("notes", value.notes.take())?;
+    Ok(Some(value))
 }
+// returns the number of records updated, which is 0 if there's no record named `term`.
+// a record that's there but given nothing to change counts as updated
+#[allow(clippy::too_many_arguments)]
 pub fn encrypt_and_update(
     connection: &mut SqliteConnection,
-    master_password: &str,
+    key: &Key,
     term: &str,
-    new_name: Option<String>,
-    new_username: Option<String>,
-    new_email: Option<String>,
-    new_pass: Option<String>,
-    new_notes: Option<String>,
-) -> Result<usize, diesel::result::Error> {
-    let nonce = Aes256Gcm::generate_nonce(OsRng);
-    let encoded_nonce = hex::encode(nonce);
-
-    let salt = new_name.clone().unwrap_or(term.to_string());
-    let encrypted_username = encrypt(master_password, new_username, nonce, &salt);
-    let encrypted_email = encrypt(master_password, new_email, nonce, &salt);
-    let encrypted_password = encrypt(master_password, new_pass, nonce, &salt);
-    let encrypted_notes = encrypt(master_password, new_notes, nonce, &salt);
+    new_name: Option<&str>,
+    new_username: Option<&str>,
+    new_email: Option<&str>,
+    new_pass: Option<&str>,
+    new_notes: Option<&str>,
+) -> Result<usize> {
+    let index = blind_index(key, term);
+    let Some(record_id) = find_record_id(connection, key, term)? else {
+        return Ok(0);
+    };
+    // diesel can't build an update that doesn't set anything, and there's nothing to do anyway
+    let changes = [new_name, new_username, new_email, new_pass, new_notes];
+    if changes.iter().all(Option::is_none) {
+        return Ok(1);
+    }
+    let encrypt_field = |field: &str, data: Option<&str>| {
+        encrypt(
+            key,
+            data,
+            &associated_data(record_id, field, RECORD_VERSION),
+        )
+    };
+    // renaming a record changes its name index too
+    let new_index = new_name.map(|n| blind_index(key, n));
+    let encrypted_name = encrypt_field("name", new_name)?;
+    let encrypted_username = encrypt_field("username", new_username)?;
+    let encrypted_email = encrypt_field("email", new_email)?;
+    let encrypted_password = encrypt_field("pass", new_pass)?;
+    let encrypted_notes = encrypt_field("notes", new_notes)?;
 
     let form = PasswordForm {
-        name: new_name.as_deref(),
+        name: encrypted_name.as_deref(),
         username: encrypted_username.as_deref(),
         email: encrypted_email.as_deref(),
         pass: encrypted_password.as_deref(),
         notes: encrypted_notes.as_deref(),
-        aes_nonce: &encoded_nonce,
+        name_index: new_index.as_deref(),
     };
-    update_password(connection, term, form)
+    update_password(connection, &index, form).map_err(|e| match (e, new_name) {
+        (Error::AlreadyExists(_), Some(renamed_to)) => {
+            Error::AlreadyExists(format!("password \"{}\"", renamed_to))
+        }
+        (e, _) => e,
+    })
 }
 
-pub fn get_all(connection: &mut SqliteConnection) -> Result<Vec<Password>, diesel::result::Error> {
-    password.load(connection)
+pub fn get_all(connection: &mut SqliteConnection) -> Result<Vec<Password>> {
+    Ok(password.load(connection)?)
+}
+// decrypts the name of every record, for listing
+pub fn get_all_names(connection: &mut SqliteConnection, key: &Key) -> Result<Vec<String>> {
+    let records = password.select(Password::as_select()).load(connection)?;
+    records
+        .into_iter()
+        .map(|record| {
+            Ok(decrypt(
+                key,
+                Some(&record.name),
+                &associated_data(record.id, "name", record.version),
+            )?
+            .map(SecretString::into_inner)
+            .unwrap_or_default())
+        })
+        .collect()
+}
+
+// re-encrypts every field of a record under `new_key`, using `decrypt_field` to read the old values.
+// `decrypt_field` is given the column name along with its value. the name index is recomputed from the decrypted name.
+fn reencrypt_record(
+    connection: &mut SqliteConnection,
+    record: &Password,
+    new_key: &Key,
+    decrypt_field: impl Fn(&str, Option<&String>) -> Result<Option<SecretString>>,
+) -> Result<usize> {
+    let reencrypt = |field: &str, data: &Option<String>| {
+        encrypt(
+            new_key,
+            decrypt_field(field, data.as_ref())?
+                .as_ref()
+                .map(SecretString::expose_secret),
+            &associated_data(record.id, field, RECORD_VERSION),
+        )
+    };
+    let plaintext_name = decrypt_field("name", Some(&record.name))?.unwrap_or_default();
+    let plaintext_name = plaintext_name.expose_secret();
+    Ok(diesel::update(password.find(record.id))
+        .set((
+            name.eq(encrypt(
+                new_key,
+                Some(plaintext_name),
+                &associated_data(record.id, "name", RECORD_VERSION),
+            )?
+            .unwrap_or_default()),
+            name_index.eq(blind_index(new_key, plaintext_name)),
+            username.eq(reencrypt("username", &record.username)?),
+            email.eq(reencrypt("email", &record.email)?),
+            pass.eq(reencrypt("pass", &record.pass)?),
+            notes.eq(reencrypt("notes", &record.notes)?),
+            aes_nonce.eq(""),
+            version.eq(RECORD_VERSION),
+        ))
+        .execute(connection)?)
+}
+
+// what upgrade_records() did
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Upgrade {
+    // the number of records that were re-encrypted
+    pub records: usize,
+    // records that had to be renamed, as (old name, new name).
+    // names used to be compared exactly, so older vaults can hold e.g. "github" and "GitHub",
+    // which would get the same name index now. the first one keeps its name, the others get a " (2)" etc. suffix.
+    pub renamed: Vec<(String, String)>,
+}
+
+// the name `record_name` is upgraded to: itself, unless its normalized form is already `taken`,
+// in which case the first free " (n)" suffix is added. the returned name is marked as taken.
+fn unclashing_name(record_name: &str, taken: &mut std::collections::HashSet<String>) -> String {
+    let mut new_name = record_name.to_string();
+    let mut suffix = 2;
+    while !taken.insert(normalize_name(&new_name)) {
+        new_name = format!("{} ({})", record_name.trim(), suffix);
+        suffix += 1;
+    }
+    new_name
+}
+
+// this function re-encrypts every record still stored with an older RECORD_VERSION.
+// it needs the master password, so it's run the first time the vault is opened after authenticating.
+// vaults from before KDF headers existed get a PBKDF2 header here, so they stay on PBKDF2 (with a random salt),
+// and vaults from before data keys existed get a new random data key. vaults without a key check value get one.
+// names that only differ in case or whitespace are renamed, see Upgrade::renamed.
+// everything happens in one transaction, so a failure leaves the old records untouched.
+pub fn upgrade_records(
+    connection: &mut SqliteConnection,
+    master_password: &str,
+) -> Result<Upgrade> {
+    connection.transaction(|connection| {
+        let outdated = password
+            .filter(version.lt(RECORD_VERSION))
+            .select(Password::as_select())
+            .load(connection)?;
+
+        let header = match get_kdf_header(connection)? {
+            Some(header) => header,
+            None => {
+                let header = KdfHeader::new(Kdf::DEFAULT_PBKDF2);
+                set_metadata(connection, KDF_METADATA_KEY, &header.to_string())?;
+                header
+            }
+        };
+        let wrapped_key = get_metadata(connection, WRAPPED_KEY_METADATA_KEY)?;
+        let has_key_check = get_metadata(connection, KEY_CHECK_METADATA_KEY)?.is_some();
+        if outdated.is_empty() && wrapped_key.is_some() && has_key_check {
+            return Ok(Upgrade::default());
+        }
+        let kek = header.derive_key(master_password)?;
+        let data_key = match wrapped_key {
+            Some(wrapped_key) => unwrap_key(&kek, &wrapped_key)?,
+            None => {
+                let data_key = generate_key();
+                set_metadata(
+                    connection,
+                    WRAPPED_KEY_METADATA_KEY,
+                    &wrap_key(&kek, &data_key)?,
+                )?;
+                data_key
+            }
+        };
+        if !has_key_check {
+            set_metadata(connection, KEY_CHECK_METADATA_KEY, &key_check(&data_key))?;
+        }
+
+        // records that are already current keep their names, so the outdated ones can't take them
+        let mut taken = std::collections::HashSet::new();
+        let current = password
+            .filter(version.eq(RECORD_VERSION))
+            .select(Password::as_select())
+            .load(connection)?;
+        for record in &current {
+            let current_name = decrypt(
+                &data_key,
+                Some(&record.name),
+                &associated_data(record.id, "name", record.version),
+            )?
+            .unwrap_or_default();
+            taken.insert(normalize_name(current_name.expose_secret()));
+        }
+        let mut renamed = Vec::new();
+        for record in &outdated {
+            // versions 0 and 1 derived a key for each record, using its name as the salt
+            let legacy_key = derive_legacy_key(master_password, &record.name)?;
+            // names were stored in plaintext before version 5
+            let new_name = unclashing_name(&record.name, &mut taken);
+            if new_name != record.name {
+                renamed.push((record.name.clone(), new_name.clone()));
+            }
+            reencrypt_record(connection, record, &data_key, |field, data| {
+                if field == "name" {
+                    return Ok(Some(SecretString::from(new_name.as_str())));
+                }
+                match record.version {
+                    // every field was encrypted with the shared aes_nonce
+                    0 => decrypt_legacy(&legacy_key, data, &record.aes_nonce),
+                    1 => decrypt(&legacy_key, data, &[]),
+                    // version 2 used the key derived from the KDF header directly
+                    2 => decrypt(&kek, data, &[]),
+                    // version 3 didn't have any associated data
+                    3 => decrypt(&data_key, data, &[]),
+                    _ => decrypt(
+                        &data_key,
+                        data,
+                        &associated_data(record.id, field, record.version),
+                    ),
+                }
+            })?;
+        }
+        Ok(Upgrade {
+            records: outdated.len(),
+            renamed,
+        })
+    })
+}
+
+// switches the vault to a new KDF (with a new salt).
+// only the data key is re-wrapped, none of the records need to be re-encrypted.
+pub fn change_kdf(
+    connection: &mut SqliteConnection,
+    master_password: &str,
+    kdf: Kdf,
+) -> Result<()> {
+    let data_key = unlock_vault_key(connection, master_password)?;
+    let header = KdfHeader::new(kdf);
+    let wrapped_key = wrap_key(&header.derive_key(master_password)?, &data_key)?;
+
+    connection.transaction(|connection| {
+        set_metadata(connection, KDF_METADATA_KEY, &header.to_string())?;
+        set_metadata(connection, WRAPPED_KEY_METADATA_KEY, &wrapped_key)?;
+        Ok(())
+    })
+}
+
+// changes the master password. the old password is checked first, and returns false if it's wrong.
+// the data key is re-wrapped under a key derived from the new password (with a new salt, same KDF),
+// with the new header in the same transaction, so none of the records need to be touched
+// and a failure partway through leaves the old master password in place.
+pub fn change_master_password(
+    connection: &mut SqliteConnection,
+    old_password: &str,
+    new_password: &str,
+) -> Result<bool> {
+    let Some(data_key) = authenticate_and_unlock(connection, old_password.as_bytes())? else {
+        return Ok(false);
+    };
+    let old_header =
+        get_kdf_header(connection)?.ok_or(Error::NotFound("KDF header".to_string()))?;
+    let header = KdfHeader::new(old_header.kdf);
+    let wrapped_key = wrap_key(&header.derive_key(new_password)?, &data_key)?;
+
+    connection.transaction(|connection| {
+        set_metadata(connection, KDF_METADATA_KEY, &header.to_string())?;
+        set_metadata(connection, WRAPPED_KEY_METADATA_KEY, &wrapped_key)?;
+        Ok(true)
+    })
 }
 
 // tests
@@ -222,25 +751,23 @@ pub fn get_all(connection: &mut SqliteConnection) -> Result<Vec<Password>, diese
 
 #[cfg(test)]
 mod tests {
+    use crate::crypto::blind_index;
+    use crate::kdf::Kdf;
     use crate::schema::password::dsl::*;
+    use crate::secret::Key;
 
     use aes_gcm::aead::{generic_array::GenericArray, Aead};
 
     use diesel::prelude::*;
     use diesel::{Connection, SqliteConnection};
-    use diesel_migrations::{embed_migrations, EmbeddedMigrations, MigrationHarness};
+    use diesel_migrations::MigrationHarness;
 
-    // the embed_migrations! macro will generate a constant value containing migrations, which are
-    // stored in the binary
-    const MIGRATIONS: EmbeddedMigrations = embed_migrations!();
     // testing only function that creates a new connection in memory,
     // and applies the migrations we generated from the embed_migrations! macro
     fn establish_in_memory_connection() -> SqliteConnection {
         let mut connection =
             SqliteConnection::establish(":memory:").expect("error establishing connection");
-        connection
-            .run_pending_migrations(MIGRATIONS)
-            .expect("error running migrations");
+        super::migrate(&mut connection).expect("error running migrations");
         connection
     }
     // testing-only function that inserts 1 test record with some data into a table
@@ -255,10 +782,49 @@ mod tests {
             pass: None,
             notes: None,
             aes_nonce: "",
+            version: super::RECORD_VERSION,
+            name_index: "test",
         };
         let _ = super::insert_password(connection, new_password);
     }
+    // testing-only function that sets the master password (and KDF header + data key) every vault needs
+    // before anything can be encrypted, then returns the unlocked data key
+    fn create_test_vault(connection: &mut SqliteConnection) -> Key {
+        super::initialize_vault(connection, b"mymasterpassword", Kdf::DEFAULT_ARGON2ID)
+            .expect("error initializing vault");
+        super::unlock_vault_key(connection, "mymasterpassword").expect("error unlocking vault")
+    }
 
+    #[test]
+    fn migrate() {
+        let mut conn = establish_in_memory_connection();
+        assert_eq!(
+            super::get_metadata(&mut conn, super::SCHEMA_VERSION_METADATA_KEY).unwrap(),
+            Some(super::SCHEMA_VERSION.to_string())
+        );
+        // every embedded migration was applied, and running it again is a no-op
+        assert!(!conn.has_pending_migration(super::MIGRATIONS).unwrap());
+        super::migrate(&mut conn).unwrap();
+        assert_eq!(
+            super::get_metadata(&mut conn, super::SCHEMA_VERSION_METADATA_KEY).unwrap(),
+            Some(super::SCHEMA_VERSION.to_string())
+        );
+    }
+    #[test]
+    fn migrate_refuses_newer_schema() {
+        let mut conn = establish_in_memory_connection();
+        let newer = (super::SCHEMA_VERSION + 1).to_string();
+        super::set_metadata(&mut conn, super::SCHEMA_VERSION_METADATA_KEY, &newer).unwrap();
+        assert!(matches!(
+            super::migrate(&mut conn),
+            Err(crate::Error::Config(_))
+        ));
+        // and the version wasn't overwritten
+        assert_eq!(
+            super::get_metadata(&mut conn, super::SCHEMA_VERSION_METADATA_KEY).unwrap(),
+            Some(newer)
+        );
+    }
     #[test]
     fn create() {
         let mut conn = establish_in_memory_connection();
@@ -272,7 +838,7 @@ mod tests {
         insert_test_data(&mut conn);
 
         let res = super::get_password(&mut conn, "test").unwrap();
-        assert_eq!(res.unwrap().username, Some("tester".to_string()));
+        assert_eq!(res.unwrap().username.as_deref(), Some("tester"));
     }
     #[test]
     fn delete() {
@@ -298,7 +864,7 @@ mod tests {
                 email: None,
                 pass: None,
                 notes: None,
-                aes_nonce: "",
+                name_index: Some("foo"),
             },
         );
         let res = super::get_password(&mut conn, "foo");
@@ -307,27 +873,195 @@ mod tests {
     #[test]
     fn authenticate() {
         let mut conn = establish_in_memory_connection();
-        let _ = super::insert_master_password(&mut conn, b"mymasterpassword");
+        create_test_vault(&mut conn);
         assert!(super::authenticate(&mut conn, b"mymasterpassword").unwrap());
     }
     #[test]
     fn failed_authentication() {
         let mut conn = establish_in_memory_connection();
-        let _ = super::insert_master_password(&mut conn, b"mymasterpassword");
+        create_test_vault(&mut conn);
         assert!(!super::authenticate(&mut conn, b"randomguess").unwrap());
     }
     #[test]
+    fn no_verifier() {
+        let mut conn = establish_in_memory_connection();
+        create_test_vault(&mut conn);
+        // the only thing that checks the master password is the wrapped data key, behind the vault's own KDF
+        assert!(super::get_metadata(&mut conn, super::VERIFIER_METADATA_KEY)
+            .unwrap()
+            .is_none());
+        let key = super::authenticate_and_unlock(&mut conn, b"mymasterpassword")
+            .unwrap()
+            .unwrap();
+        assert!(super::check_vault_key(&mut conn, &key).unwrap());
+        assert!(super::authenticate_and_unlock(&mut conn, b"randomguess")
+            .unwrap()
+            .is_none());
+    }
+    #[test]
+    fn leftover_verifier_is_removed() {
+        let mut conn = establish_in_memory_connection();
+        create_test_vault(&mut conn);
+        // vaults that got a data key while they still had a verifier kept both
+        let verifier = crate::crypto::hash_master_password("mymasterpassword").unwrap();
+        super::set_metadata(&mut conn, super::VERIFIER_METADATA_KEY, &verifier).unwrap();
+        // a wrong password doesn't touch it
+        assert!(!super::authenticate(&mut conn, b"randomguess").unwrap());
+        assert!(super::get_metadata(&mut conn, super::VERIFIER_METADATA_KEY)
+            .unwrap()
+            .is_some());
+        assert!(super::authenticate(&mut conn, b"mymasterpassword").unwrap());
+        assert!(super::get_metadata(&mut conn, super::VERIFIER_METADATA_KEY)
+            .unwrap()
+            .is_none());
+        assert!(super::is_initialized(&mut conn).unwrap());
+    }
+    #[test]
+    fn initialize_twice() {
+        let mut conn = establish_in_memory_connection();
+        assert!(!super::is_initialized(&mut conn).unwrap());
+        create_test_vault(&mut conn);
+        assert!(super::is_initialized(&mut conn).unwrap());
+        assert!(matches!(
+            super::initialize_vault(&mut conn, b"randomguess", Kdf::DEFAULT_ARGON2ID),
+            Err(crate::Error::AlreadyExists(_))
+        ));
+        assert!(super::authenticate(&mut conn, b"mymasterpassword").unwrap());
+    }
+    #[test]
+    fn migrate_moves_master_record() {
+        use crate::models::NewPassword;
+        let mut conn =
+            SqliteConnection::establish(":memory:").expect("error establishing connection");
+        // apply every migration up to (but not including) the one that moved the verifier
+        for _ in 0..4 {
+            conn.run_next_migration(super::MIGRATIONS).unwrap();
+        }
+        let verifier = crate::crypto::hash_master_password("mymasterpassword").unwrap();
+        for (record_name, record_pass) in [(".master", verifier.as_str()), ("abcd", "")] {
+            super::insert_password(
+                &mut conn,
+                NewPassword {
+                    name: record_name,
+                    username: None,
+                    email: None,
+                    pass: Some(record_pass),
+                    notes: None,
+                    aes_nonce: "",
+                    version: 4,
+                    name_index: record_name,
+                },
+            )
+            .unwrap();
+        }
+        super::migrate(&mut conn).unwrap();
+        assert!(!super::authenticate(&mut conn, b"randomguess").unwrap());
+        assert!(super::authenticate(&mut conn, b"mymasterpassword").unwrap());
+        // the master record is gone, other records are untouched
+        assert!(super::get_password(&mut conn, ".master").unwrap().is_none());
+        assert!(super::get_password(&mut conn, "abcd").unwrap().is_some());
+    }
+    #[test]
+    fn authenticate_replaces_legacy_verifier() {
+        let mut conn = establish_in_memory_connection();
+        // older vaults stored a plain SHA-256 hash of the master password
+        let legacy = hex::encode(crate::crypto::hash(b"mymasterpassword"));
+        super::set_metadata(&mut conn, super::VERIFIER_METADATA_KEY, &legacy).unwrap();
+        // a wrong guess shouldn't upgrade anything
+        assert!(!super::authenticate(&mut conn, b"randomguess").unwrap());
+        let verifier = super::get_metadata(&mut conn, super::VERIFIER_METADATA_KEY).unwrap();
+        assert_eq!(verifier.unwrap(), legacy);
+
+        assert!(super::authenticate(&mut conn, b"mymasterpassword").unwrap());
+        // the verifier is replaced by a data key, wrapped with PBKDF2 like the rest of these vaults
+        let verifier = super::get_metadata(&mut conn, super::VERIFIER_METADATA_KEY).unwrap();
+        assert!(verifier.is_none());
+        let header = super::get_kdf_header(&mut conn).unwrap().unwrap();
+        assert_eq!(header.kdf, Kdf::DEFAULT_PBKDF2);
+        // and the data key still works
+        assert!(super::authenticate(&mut conn, b"mymasterpassword").unwrap());
+        assert!(!super::authenticate(&mut conn, b"randomguess").unwrap());
+    }
+    #[test]
+    fn authenticate_legacy_verifier_mismatch() {
+        let mut conn = establish_in_memory_connection();
+        // a hash that only differs in its last byte, and one that's been truncated,
+        // both go through the constant time comparison and are rejected without upgrading
+        let mut legacy = crate::crypto::hash(b"mymasterpassword").to_vec();
+        *legacy.last_mut().unwrap() ^= 1;
+        for verifier in [hex::encode(&legacy), hex::encode(&legacy[..16])] {
+            super::set_metadata(&mut conn, super::VERIFIER_METADATA_KEY, &verifier).unwrap();
+            assert!(!super::authenticate(&mut conn, b"mymasterpassword").unwrap());
+            let stored = super::get_metadata(&mut conn, super::VERIFIER_METADATA_KEY).unwrap();
+            assert_eq!(stored.unwrap(), verifier);
+        }
+    }
+    #[test]
+    fn failed_unlocks_are_counted() {
+        let mut conn = establish_in_memory_connection();
+        create_test_vault(&mut conn);
+        assert_eq!(super::get_failed_unlocks(&mut conn).unwrap(), (0, None));
+
+        assert!(!super::authenticate(&mut conn, b"randomguess").unwrap());
+        assert!(!super::authenticate(&mut conn, b"randomguess").unwrap());
+        let (failures, last_failure) = super::get_failed_unlocks(&mut conn).unwrap();
+        assert_eq!(failures, 2);
+        assert!(last_failure.is_some());
+        // still within the free attempts
+        assert_eq!(
+            super::remaining_unlock_delay(&mut conn).unwrap(),
+            std::time::Duration::ZERO
+        );
+
+        // a successful unlock resets the count, but the last failure is still there to look at
+        assert!(super::authenticate(&mut conn, b"mymasterpassword").unwrap());
+        assert_eq!(
+            super::get_failed_unlocks(&mut conn).unwrap(),
+            (0, last_failure)
+        );
+    }
+    #[test]
+    fn failed_unlocks_back_off() {
+        use std::time::Duration;
+        let mut conn = establish_in_memory_connection();
+        create_test_vault(&mut conn);
+        for _ in 0..super::FREE_UNLOCK_ATTEMPTS + 2 {
+            super::record_failed_unlock(&mut conn).unwrap();
+        }
+        let remaining = super::remaining_unlock_delay(&mut conn).unwrap();
+        assert!(remaining > Duration::from_secs(2) && remaining <= Duration::from_secs(4));
+
+        assert_eq!(super::unlock_delay(0), Duration::ZERO);
+        assert_eq!(
+            super::unlock_delay(super::FREE_UNLOCK_ATTEMPTS - 1),
+            Duration::ZERO
+        );
+        assert_eq!(
+            super::unlock_delay(super::FREE_UNLOCK_ATTEMPTS),
+            Duration::from_secs(1)
+        );
+        assert_eq!(
+            super::unlock_delay(super::FREE_UNLOCK_ATTEMPTS + 3),
+            Duration::from_secs(8)
+        );
+        assert_eq!(super::unlock_delay(u32::MAX), super::MAX_UNLOCK_DELAY);
+    }
+    #[test]
     fn encrypt_and_insert_password() {
         use crate::models::Password;
         use aes_gcm::{Aes256Gcm, Key, KeyInit};
 
         let mut conn = establish_in_memory_connection();
+        // i was too lazy to get a new key so i just copied the pbkdf2 string literal from crypto.rs tests
+        let raw_key: [u8; 32] =
+            hex_literal::hex!("8f21affeb61e304e7b474229ffeb34309ed31beda58d153bc7ad9da6e9b6184c");
+        let vault_key = crate::secret::Key::from_bytes(&raw_key);
         // this is the function we are testing
         let _ = super::encrypt_and_insert(
             &mut conn,
-            "mymasterpassword",
-            "salt", // note that i put salt here because i have the pbkdf2 string literal below derived with "salt" as the salt..
-            Some("tester1".to_string()),
+            &vault_key,
+            "salt",
+            Some("tester1"),
             None,
             None,
             None,
@@ -336,82 +1070,624 @@ mod tests {
         // ideally this code should use as few of my own functions as possible
         // idk if this is true, it just seems smart to me
         let res: Password = password
-            .filter(name.eq("salt"))
+            .filter(name_index.eq(blind_index(&vault_key, "salt")))
             .select(Password::as_select())
             .first(&mut conn)
             .expect("error getting password");
         //unwrap hell
-        let ciphertext = hex::decode(res.username.unwrap()).unwrap();
-        let decoded = hex::decode(res.aes_nonce).unwrap();
-        let nonce = GenericArray::from_slice(&decoded);
+        let blob = hex::decode(res.username.as_ref().unwrap()).unwrap();
+        // every field starts with its own nonce
+        let (decoded, ciphertext) = blob.split_at(12);
+        let nonce = GenericArray::from_slice(decoded);
+        // and is bound to its version, record id and column
+        let aad = format!("pwd-rs:{}:{}:username", super::RECORD_VERSION, res.id);
+        let payload = aes_gcm::aead::Payload {
+            msg: ciphertext,
+            aad: aad.as_bytes(),
+        };
 
-        // i was too lazy to get a new pbkdf2 string literal so i just copied one from crypto.rs tests
-        let key = hex::decode("8f21affeb61e304e7b474229ffeb34309ed31beda58d153bc7ad9da6e9b6184c")
-            .unwrap();
-        let key = Key::<Aes256Gcm>::from_slice(&key);
+        let key = Key::<Aes256Gcm>::from_slice(&raw_key);
         let cipher = Aes256Gcm::new(key);
 
         // decrypt here, too lazy to write good expect()'s
-        let val = cipher.decrypt(nonce, ciphertext.as_ref()).expect("ERROR!");
+        let val = cipher.decrypt(nonce, payload).expect("ERROR!");
 
         assert_eq!(val, b"tester1");
     }
-
     #[test]
-    fn read_and_decrypt() {
+    fn encrypt_and_insert_unique_nonces() {
         let mut conn = establish_in_memory_connection();
-        let master = "mymasterpassword";
-        let term = "abcd";
+        let key = create_test_vault(&mut conn);
         let _ = super::encrypt_and_insert(
             &mut conn,
-            master,
-            term,
-            Some("tester1".to_string()),
-            None,
+            &key,
+            "abcd",
+            Some("same"),
+            Some("same"),
             None,
             None,
         )
         .expect("error inserting password");
-        let res = super::read_and_decrypt(&mut conn, master, term).expect("error decrypting");
+        let res = super::get_password(&mut conn, &blind_index(&key, "abcd"))
+            .unwrap()
+            .unwrap();
+        // same plaintext, but each field got a different nonce, so the ciphertexts can't match
+        assert_ne!(
+            res.username.as_ref().unwrap()[..24],
+            res.email.as_ref().unwrap()[..24]
+        );
+    }
+    #[test]
+    fn upgrade_records() {
+        use crate::kdf::Kdf;
+        use crate::models::NewPassword;
+        use aes_gcm::{AeadCore, Aes256Gcm, Key, KeyInit};
+
+        let mut conn = establish_in_memory_connection();
+        // build a record the way version 0 did, with one nonce for every field
+        // and the record's name as the pbkdf2 salt
+        let nonce = Aes256Gcm::generate_nonce(&mut aes_gcm::aead::OsRng);
+        let key = hex::decode("8f21affeb61e304e7b474229ffeb34309ed31beda58d153bc7ad9da6e9b6184c")
+            .unwrap();
+        let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key));
+        let old_username = hex::encode(cipher.encrypt(&nonce, b"tester1".as_ref()).unwrap());
+        let old_pass = hex::encode(cipher.encrypt(&nonce, b"hunter2".as_ref()).unwrap());
+        let _ = super::insert_password(
+            &mut conn,
+            NewPassword {
+                name: "salt",
+                username: Some(&old_username),
+                email: None,
+                pass: Some(&old_pass),
+                notes: None,
+                aes_nonce: &hex::encode(nonce),
+                version: 0,
+                name_index: "salt",
+            },
+        );
+        // and one the way version 1 did, with a nonce per field but still salted with the name
+        let legacy_key = crate::crypto::derive_legacy_key("mymasterpassword", "other").unwrap();
+        let other_notes = crate::crypto::encrypt(&legacy_key, Some("some notes"), &[])
+            .unwrap()
+            .unwrap();
+        let _ = super::insert_password(
+            &mut conn,
+            NewPassword {
+                name: "other",
+                username: None,
+                email: None,
+                pass: None,
+                notes: Some(&other_notes),
+                aes_nonce: "",
+                version: 1,
+                name_index: "other",
+            },
+        );
+
+        let upgraded = super::upgrade_records(&mut conn, "mymasterpassword").unwrap();
+        assert_eq!(upgraded.records, 2);
+        assert!(upgraded.renamed.is_empty());
+        // running it again shouldn't touch anything
         assert_eq!(
-            res.expect("error: was None")
-                .username
-                .expect("error: no username"),
-            "tester1".to_string()
+            super::upgrade_records(&mut conn, "mymasterpassword")
+                .unwrap()
+                .records,
+            0
         );
+        // old vaults stay on pbkdf2
+        let header = super::get_kdf_header(&mut conn).unwrap().unwrap();
+        assert_eq!(header.kdf, Kdf::DEFAULT_PBKDF2);
+
+        let key = super::unlock_vault_key(&mut conn, "mymasterpassword").unwrap();
+        let res = super::read_and_decrypt(&mut conn, &key, "salt")
+            .unwrap()
+            .unwrap();
+        assert_eq!(res.version, super::RECORD_VERSION);
+        assert_eq!(res.name, "salt");
+        assert_ne!(res.name_index, "salt");
+        assert_eq!(res.username.as_deref(), Some("tester1"));
+        assert_eq!(res.pass.as_deref(), Some("hunter2"));
+        let res = super::read_and_decrypt(&mut conn, &key, "other")
+            .unwrap()
+            .unwrap();
+        assert_eq!(res.notes.as_deref(), Some("some notes"));
     }
     #[test]
-    fn encypt_and_update() {
+    fn upgrade_records_to_data_key() {
+        use crate::models::NewPassword;
+
+        let mut conn = establish_in_memory_connection();
+        // version 2 records were encrypted directly with the key derived from the KDF header
+        super::set_metadata(
+            &mut conn,
+            super::KDF_METADATA_KEY,
+            "pbkdf2-sha256$i=1000$73616c74",
+        )
+        .unwrap();
+        let header = super::get_kdf_header(&mut conn).unwrap().unwrap();
+        let derived_key = header.derive_key("mymasterpassword").unwrap();
+        let old_pass = crate::crypto::encrypt(&derived_key, Some("hunter2"), &[])
+            .unwrap()
+            .unwrap();
+        let _ = super::insert_password(
+            &mut conn,
+            NewPassword {
+                name: "abcd",
+                username: None,
+                email: None,
+                pass: Some(&old_pass),
+                notes: None,
+                aes_nonce: "",
+                version: 2,
+                name_index: "abcd",
+            },
+        );
+
+        assert_eq!(
+            super::upgrade_records(&mut conn, "mymasterpassword")
+                .unwrap()
+                .records,
+            1
+        );
+        // the existing header is kept, and now wraps a data key
+        assert_eq!(super::get_kdf_header(&mut conn).unwrap().unwrap(), header);
+        let key = super::unlock_vault_key(&mut conn, "mymasterpassword").unwrap();
+        assert_ne!(key, derived_key);
+        let res = super::read_and_decrypt(&mut conn, &key, "abcd")
+            .unwrap()
+            .unwrap();
+        assert_eq!(res.pass.as_deref(), Some("hunter2"));
+    }
+    #[test]
+    fn upgrade_records_adds_associated_data() {
+        use crate::models::NewPassword;
+
         let mut conn = establish_in_memory_connection();
+        let key = create_test_vault(&mut conn);
+        // version 3 records used the data key, but without any associated data
+        let old_pass = crate::crypto::encrypt(&key, Some("hunter2"), &[])
+            .unwrap()
+            .unwrap();
+        let _ = super::insert_password(
+            &mut conn,
+            NewPassword {
+                name: "abcd",
+                username: None,
+                email: None,
+                pass: Some(&old_pass),
+                notes: None,
+                aes_nonce: "",
+                version: 3,
+                name_index: "abcd",
+            },
+        );
+        assert_eq!(
+            super::upgrade_records(&mut conn, "mymasterpassword")
+                .unwrap()
+                .records,
+            1
+        );
+        let res = super::read_and_decrypt(&mut conn, &key, "abcd")
+            .unwrap()
+            .unwrap();
+        assert_eq!(res.version, super::RECORD_VERSION);
+        assert_eq!(res.pass.as_deref(), Some("hunter2"));
+    }
+    #[test]
+    fn upgrade_records_encrypts_names() {
+        use crate::models::NewPassword;
+
+        let mut conn = establish_in_memory_connection();
+        let key = create_test_vault(&mut conn);
+        // version 4 records had associated data, but a plaintext name (which the migration copied into name_index).
+        // it will be the first row in the table, so its id is 1
+        let old_pass = crate::crypto::encrypt(
+            &key,
+            Some("hunter2"),
+            &crate::crypto::associated_data(1, "pass", 4),
+        )
+        .unwrap()
+        .unwrap();
+        let _ = super::insert_password(
+            &mut conn,
+            NewPassword {
+                name: "abcd",
+                username: None,
+                email: None,
+                pass: Some(&old_pass),
+                notes: None,
+                aes_nonce: "",
+                version: 4,
+                name_index: "abcd",
+            },
+        );
+        assert_eq!(
+            super::upgrade_records(&mut conn, "mymasterpassword")
+                .unwrap()
+                .records,
+            1
+        );
+        let record = super::get_password(&mut conn, &blind_index(&key, "abcd"))
+            .unwrap()
+            .unwrap();
+        assert_ne!(record.name, "abcd");
+        let res = super::read_and_decrypt(&mut conn, &key, "abcd")
+            .unwrap()
+            .unwrap();
+        assert_eq!(res.name, "abcd");
+        assert_eq!(res.pass.as_deref(), Some("hunter2"));
+    }
+    #[test]
+    fn upgrade_records_renames_clashing_names() {
+        use crate::models::NewPassword;
+
+        let mut conn = establish_in_memory_connection();
+        let key = create_test_vault(&mut conn);
+        super::encrypt_and_insert(&mut conn, &key, "gitlab", None, None, None, None).unwrap();
+        // names used to be compared exactly, so these could all be added before version 5
+        for (i, record_name) in ["github", "GitHub", " github ", "GitLab"]
+            .iter()
+            .enumerate()
+        {
+            let record_id = i as i32 + 2;
+            let old_pass = crate::crypto::encrypt(
+                &key,
+                Some(&format!("pass{}", record_id)),
+                &crate::crypto::associated_data(record_id, "pass", 4),
+            )
+            .unwrap()
+            .unwrap();
+            super::insert_password(
+                &mut conn,
+                NewPassword {
+                    name: record_name,
+                    username: None,
+                    email: None,
+                    pass: Some(&old_pass),
+                    notes: None,
+                    aes_nonce: "",
+                    version: 4,
+                    name_index: record_name,
+                },
+            )
+            .unwrap();
+        }
+        let upgraded = super::upgrade_records(&mut conn, "mymasterpassword").unwrap();
+        assert_eq!(upgraded.records, 4);
+        assert_eq!(
+            upgraded.renamed,
+            vec![
+                ("GitHub".to_string(), "GitHub (2)".to_string()),
+                (" github ".to_string(), "github (3)".to_string()),
+                ("GitLab".to_string(), "GitLab (2)".to_string()),
+            ]
+        );
+        // every record can still be found, under its new name
+        for (record_name, record_pass) in [
+            ("github", "pass2"),
+            ("github (2)", "pass3"),
+            ("github (3)", "pass4"),
+            ("gitlab (2)", "pass5"),
+        ] {
+            let res = super::read_and_decrypt(&mut conn, &key, record_name)
+                .unwrap()
+                .unwrap();
+            assert_eq!(res.pass.as_deref(), Some(record_pass));
+        }
+        assert_eq!(
+            super::read_and_decrypt(&mut conn, &key, "github (2)")
+                .unwrap()
+                .unwrap()
+                .name,
+            "GitHub (2)"
+        );
+    }
+    #[test]
+    fn names_are_encrypted() {
+        let mut conn = establish_in_memory_connection();
+        let key = create_test_vault(&mut conn);
+        for record_name in ["github", "gitlab"] {
+            let _ = super::encrypt_and_insert(&mut conn, &key, record_name, None, None, None, None)
+                .unwrap();
+        }
+        // nothing in the table gives the names away
+        let rows = super::get_all(&mut conn).unwrap();
+        assert!(rows
+            .iter()
+            .all(|row| !row.name.contains("git") && !row.name_index.contains("git")));
+
+        let index = blind_index(&key, "GitHub");
+        assert!(super::check_password_exists(&mut conn, &index).unwrap());
+        let mut names = super::get_all_names(&mut conn, &key).unwrap();
+        names.sort();
+        assert_eq!(names, vec!["github", "gitlab"]);
+
+        super::delete_password(&mut conn, &index).unwrap();
+        assert!(!super::check_password_exists(&mut conn, &index).unwrap());
+        assert_eq!(
+            super::get_all_names(&mut conn, &key).unwrap(),
+            vec!["gitlab"]
+        );
+    }
+    #[test]
+    fn duplicate_names() {
+        let mut conn = establish_in_memory_connection();
+        let key = create_test_vault(&mut conn);
+        for record_name in ["abcd", "efgh"] {
+            super::encrypt_and_insert(&mut conn, &key, record_name, None, None, None, None)
+                .unwrap();
+        }
+        // names are compared after normalizing them
+        assert!(matches!(
+            super::encrypt_and_insert(&mut conn, &key, "ABCD", None, None, None, None),
+            Err(crate::Error::AlreadyExists(_))
+        ));
+        assert!(matches!(
+            super::encrypt_and_update(
+                &mut conn,
+                &key,
+                "efgh",
+                Some("abcd"),
+                None,
+                None,
+                None,
+                None
+            ),
+            Err(crate::Error::AlreadyExists(_))
+        ));
+    }
+    #[test]
+    fn missing_master_record() {
+        let mut conn = establish_in_memory_connection();
+        assert!(matches!(
+            super::authenticate(&mut conn, b"mymasterpassword"),
+            Err(crate::Error::NotFound(_))
+        ));
+    }
+    #[test]
+    fn new_vaults_use_argon2id() {
+        use crate::kdf::Kdf;
+        let mut conn = establish_in_memory_connection();
+        create_test_vault(&mut conn);
+        let header = super::get_kdf_header(&mut conn).unwrap().unwrap();
+        assert_eq!(header.kdf, Kdf::DEFAULT_ARGON2ID);
+    }
+    #[test]
+    fn change_kdf() {
+        use crate::kdf::Kdf;
+        let mut conn = establish_in_memory_connection();
+        let key = create_test_vault(&mut conn);
         let master = "mymasterpassword";
-        let term = "abcd";
+        let _ =
+            super::encrypt_and_insert(&mut conn, &key, "abcd", None, None, Some("hunter2"), None)
+                .unwrap();
+        let old_header = super::get_kdf_header(&mut conn).unwrap().unwrap();
+        let old_record = super::get_password(&mut conn, &blind_index(&key, "abcd"))
+            .unwrap()
+            .unwrap();
+
+        let kdf = Kdf::Pbkdf2 { iterations: 1000 };
+        super::change_kdf(&mut conn, master, kdf).unwrap();
+
+        let header = super::get_kdf_header(&mut conn).unwrap().unwrap();
+        assert_eq!(header.kdf, kdf);
+        assert_ne!(header.salt, old_header.salt);
+        // the data key is the same, so the record itself wasn't touched
+        assert_eq!(super::unlock_vault_key(&mut conn, master).unwrap(), key);
+        let record = super::get_password(&mut conn, &blind_index(&key, "abcd"))
+            .unwrap()
+            .unwrap();
+        assert_eq!(record.pass, old_record.pass);
+        let res = super::read_and_decrypt(&mut conn, &key, "abcd")
+            .unwrap()
+            .unwrap();
+        assert_eq!(res.pass.as_deref(), Some("hunter2"));
+    }
+
+    #[test]
+    fn change_master_password() {
+        let mut conn = establish_in_memory_connection();
+        let key = create_test_vault(&mut conn);
+        let _ =
+            super::encrypt_and_insert(&mut conn, &key, "abcd", None, None, Some("hunter2"), None)
+                .unwrap();
+        let old_header = super::get_kdf_header(&mut conn).unwrap().unwrap();
+
+        // the old password has to be right
+        assert!(!super::change_master_password(&mut conn, "randomguess", "newpassword").unwrap());
+        assert!(super::authenticate(&mut conn, b"mymasterpassword").unwrap());
+
+        assert!(
+            super::change_master_password(&mut conn, "mymasterpassword", "newpassword").unwrap()
+        );
+        assert!(!super::authenticate(&mut conn, b"mymasterpassword").unwrap());
+        assert!(super::authenticate(&mut conn, b"newpassword").unwrap());
+
+        // same KDF, new salt, same data key
+        let header = super::get_kdf_header(&mut conn).unwrap().unwrap();
+        assert_eq!(header.kdf, old_header.kdf);
+        assert_ne!(header.salt, old_header.salt);
+        let new_key = super::unlock_vault_key(&mut conn, "newpassword").unwrap();
+        assert_eq!(new_key, key);
+        let res = super::read_and_decrypt(&mut conn, &new_key, "abcd")
+            .unwrap()
+            .unwrap();
+        assert_eq!(res.pass.as_deref(), Some("hunter2"));
+    }
+    #[test]
+    fn backup_database() {
+        let mut conn = establish_in_memory_connection();
+        create_test_vault(&mut conn);
+        let destination =
+            std::env::temp_dir().join(format!("pwd-rs-backup-test-{}.db", std::process::id()));
+        let destination = destination.to_str().unwrap();
+        let _ = std::fs::remove_file(destination);
+
+        super::backup_database(&mut conn, destination).unwrap();
+        let mut backup = SqliteConnection::establish(destination).unwrap();
+        assert!(super::authenticate(&mut backup, b"mymasterpassword").unwrap());
+
+        drop(backup);
+        std::fs::remove_file(destination).unwrap();
+    }
+    #[test]
+    fn swapped_fields_fail_to_decrypt() {
+        let mut conn = establish_in_memory_connection();
+        let key = create_test_vault(&mut conn);
         let _ = super::encrypt_and_insert(
             &mut conn,
-            master,
-            term,
-            Some("tester1".to_string()),
-            None,
+            &key,
+            "abcd",
             None,
             None,
+            Some("hunter2"),
+            Some("some notes"),
         )
-        .expect("error inserting password");
+        .unwrap();
+        // someone with write access to the database moves the password into the notes column
+        let record = super::get_password(&mut conn, &blind_index(&key, "abcd"))
+            .unwrap()
+            .unwrap();
+        diesel::update(password.find(record.id))
+            .set(notes.eq(&record.pass))
+            .execute(&mut conn)
+            .unwrap();
+        assert!(matches!(
+            super::read_and_decrypt(&mut conn, &key, "abcd"),
+            Err(crate::Error::Decryption)
+        ));
+    }
+    #[test]
+    fn swapped_records_fail_to_decrypt() {
+        let mut conn = establish_in_memory_connection();
+        let key = create_test_vault(&mut conn);
+        for (record_name, record_pass) in [("abcd", "hunter2"), ("efgh", "letmein")] {
+            let _ = super::encrypt_and_insert(
+                &mut conn,
+                &key,
+                record_name,
+                None,
+                None,
+                Some(record_pass),
+                None,
+            )
+            .unwrap();
+        }
+        // copy one record's password over another's
+        let record = super::get_password(&mut conn, &blind_index(&key, "abcd"))
+            .unwrap()
+            .unwrap();
+        diesel::update(password.filter(name_index.eq(blind_index(&key, "efgh"))))
+            .set(pass.eq(&record.pass))
+            .execute(&mut conn)
+            .unwrap();
+        assert!(matches!(
+            super::read_and_decrypt(&mut conn, &key, "efgh"),
+            Err(crate::Error::Decryption)
+        ));
+    }
+    #[test]
+    fn update_without_changes() {
+        let mut conn = establish_in_memory_connection();
+        let key = create_test_vault(&mut conn);
+        let _ =
+            super::encrypt_and_insert(&mut conn, &key, "abcd", Some("tester1"), None, None, None)
+                .unwrap();
+        let unchanged = |conn: &mut SqliteConnection, term: &str| {
+            super::encrypt_and_update(conn, &key, term, None, None, None, None, None).unwrap()
+        };
+        assert_eq!(unchanged(&mut conn, "abcd"), 1);
+        assert_eq!(unchanged(&mut conn, "efgh"), 0);
+        let res = super::read_and_decrypt(&mut conn, &key, "abcd")
+            .unwrap()
+            .unwrap();
+        assert_eq!(res.username.as_deref(), Some("tester1"));
+    }
+    #[test]
+    fn swapped_name_indexes_are_refused() {
+        let mut conn = establish_in_memory_connection();
+        let key = create_test_vault(&mut conn);
+        for (record_name, record_pass) in [("site", "hunter2"), ("other", "letmein")] {
+            let _ = super::encrypt_and_insert(
+                &mut conn,
+                &key,
+                record_name,
+                None,
+                None,
+                Some(record_pass),
+                None,
+            )
+            .unwrap();
+        }
+        // swap the two records' name indexes, going through a placeholder because they're unique
+        let (site, other) = (blind_index(&key, "site"), blind_index(&key, "other"));
+        for (from, to) in [
+            (site.as_str(), "placeholder"),
+            (other.as_str(), site.as_str()),
+            ("placeholder", other.as_str()),
+        ] {
+            diesel::update(password.filter(name_index.eq(from)))
+                .set(name_index.eq(to))
+                .execute(&mut conn)
+                .unwrap();
+        }
+        assert!(matches!(
+            super::read_and_decrypt(&mut conn, &key, "site"),
+            Err(crate::Error::Decryption)
+        ));
+        assert!(matches!(
+            super::encrypt_and_update(&mut conn, &key, "site", None, None, None, Some("x"), None),
+            Err(crate::Error::Decryption)
+        ));
+        assert!(matches!(
+            super::delete_record(&mut conn, &key, "site"),
+            Err(crate::Error::Decryption)
+        ));
+        assert_eq!(super::get_all(&mut conn).unwrap().len(), 2);
+    }
+    #[test]
+    fn read_and_decrypt() {
+        let mut conn = establish_in_memory_connection();
+        let key = create_test_vault(&mut conn);
+        let term = "abcd";
+        let _ = super::encrypt_and_insert(&mut conn, &key, term, Some("tester1"), None, None, None)
+            .expect("error inserting password");
+        let res = super::read_and_decrypt(&mut conn, &key, term).expect("error decrypting");
+        assert_eq!(
+            res.expect("error: was None")
+                .username
+                .as_deref()
+                .expect("error: no username"),
+            "tester1"
+        );
+    }
+    #[test]
+    fn encypt_and_update() {
+        let mut conn = establish_in_memory_connection();
+        let key = create_test_vault(&mut conn);
+        let term = "abcd";
+        let _ = super::encrypt_and_insert(&mut conn, &key, term, Some("tester1"), None, None, None)
+            .expect("error inserting password");
 
         // update some different fields and coolness ensues
         let _ = super::encrypt_and_update(
             &mut conn,
-            master,
+            &key,
             term,
-            Some("efgh".to_string()),
-            Some("tester2".to_string()),
+            Some("efgh"),
+            Some("tester2"),
             None,
-            Some("topsecretpassword".to_string()),
-            Some("I ADDED NOTES???".to_string()),
+            Some("topsecretpassword"),
+            Some("I ADDED NOTES???"),
         )
         .unwrap();
-        let res = super::read_and_decrypt(&mut conn, master, "efgh").expect("error decrypting");
+        let res = super::read_and_decrypt(&mut conn, &key, "efgh").expect("error decrypting");
         assert_eq!(
-            res.expect("error: was None").pass.expect("error: no pass"),
-            "topsecretpassword".to_string()
+            res.expect("error: was None")
+                .pass
+                .as_deref()
+                .expect("error: no pass"),
+            "topsecretpassword"
         );
     }
 }
@@ -1,5 +1,12 @@
 // @generated automatically by Diesel CLI.
 
+diesel::table! {
+    metadata (key) {
+        key -> Text,
+        value -> Text,
+    }
+}
+
 diesel::table! {
     password (id) {
         id -> Integer,
@@ -9,5 +16,9 @@ diesel::table! {
         pass -> Nullable<Text>,
         notes -> Nullable<Text>,
         aes_nonce -> Text,
+        version -> Integer,
+        name_index -> Text,
     }
 }
+
+diesel::allow_tables_to_appear_in_same_query!(metadata, password,);
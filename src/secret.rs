@@ -0,0 +1,174 @@
+// types for holding secrets (keys, passwords, decrypted data) in memory.
+// they're wiped when dropped, and never show up in Debug output.
+
+use std::fmt;
+
+use zeroize::{Zeroize, ZeroizeOnDrop};
+
+/// A 256-bit key (a derived key, or a vault's data key).
+/// It lives in its own allocation, which is locked into memory where the platform allows it
+/// so it can't be swapped to disk, and it's zeroed when dropped.
+pub struct Key {
+    bytes: Box<[u8; 32]>,
+}
+
+impl Key {
+    /// Creates a key that's all zeroes, to be filled in through `as_mut_bytes()`.
+    /// Filling a key in place avoids leaving copies of it on the stack.
+    pub fn zeroed() -> Self {
+        let bytes = Box::new([0u8; 32]);
+        lock_memory(bytes.as_ptr(), bytes.len());
+        Key { bytes }
+    }
+    /// Creates a key from `bytes`. The caller is responsible for wiping its own copy.
+    pub fn from_bytes(bytes: &[u8; 32]) -> Self {
+        let mut key = Key::zeroed();
+        key.as_mut_bytes().copy_from_slice(bytes);
+        key
+    }
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.bytes
+    }
+    pub fn as_mut_bytes(&mut self) -> &mut [u8; 32] {
+        &mut self.bytes
+    }
+}
+
+impl Drop for Key {
+    fn drop(&mut self) {
+        self.bytes.zeroize();
+        unlock_memory(self.bytes.as_ptr(), self.bytes.len());
+    }
+}
+
+impl Clone for Key {
+    fn clone(&self) -> Self {
+        Key::from_bytes(&self.bytes)
+    }
+}
+
+impl PartialEq for Key {
+    fn eq(&self, other: &Self) -> bool {
+        crate::crypto::constant_time_eq(self.as_bytes(), other.as_bytes())
+    }
+}
+impl Eq for Key {}
+
+impl fmt::Debug for Key {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Key([redacted])")
+    }
+}
+
+/// A string holding a secret, like a password or decrypted data. It's zeroed when dropped.
+#[derive(Clone, Default, Zeroize, ZeroizeOnDrop)]
+pub struct SecretString(String);
+
+impl SecretString {
+    pub fn new(secret: String) -> Self {
+        SecretString(secret)
+    }
+    /// Returns the secret itself. Try not to copy it anywhere that isn't wiped too.
+    pub fn expose_secret(&self) -> &str {
+        &self.0
+    }
+    /// Moves the secret out, for storing in another type that wipes itself
+    /// (like `models::Password` or `vault::Entry`).
+    pub fn into_inner(mut self) -> String {
+        std::mem::take(&mut self.0)
+    }
+}
+
+// compared in constant time, like Key
+impl PartialEq for SecretString {
+    fn eq(&self, other: &Self) -> bool {
+        crate::crypto::constant_time_eq(self.expose_secret(), other.expose_secret())
+    }
+}
+impl Eq for SecretString {}
+
+impl From<String> for SecretString {
+    fn from(secret: String) -> Self {
+        SecretString(secret)
+    }
+}
+impl From<&str> for SecretString {
+    fn from(secret: &str) -> Self {
+        SecretString(secret.to_string())
+    }
+}
+
+impl fmt::Debug for SecretString {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "SecretString([redacted])")
+    }
+}
+
+/// Stops this process from writing core dumps (and, on Linux, from being attached to with ptrace
+/// by other processes of the same user), so secrets in memory can't end up on disk that way.
+/// This is best effort: it does nothing on platforms that don't support it.
+pub fn disable_core_dumps() {
+    #[cfg(unix)]
+    {
+        let limit = libc::rlimit {
+            rlim_cur: 0,
+            rlim_max: 0,
+        };
+        // SAFETY: setrlimit only reads the struct we pass in
+        unsafe {
+            libc::setrlimit(libc::RLIMIT_CORE, &limit);
+        }
+    }
+    #[cfg(target_os = "linux")]
+    // SAFETY: PR_SET_DUMPABLE takes a single integer argument and doesn't touch memory
+    unsafe {
+        libc::prctl(libc::PR_SET_DUMPABLE, 0, 0, 0, 0);
+    }
+}
+
+// locking can fail (e.g. RLIMIT_MEMLOCK is 0), in which case the key just isn't locked
+#[cfg(unix)]
+fn lock_memory(ptr: *const u8, len: usize) {
+    // SAFETY: the range is a live allocation owned by the caller
+    unsafe {
+        libc::mlock(ptr as *const libc::c_void, len);
+    }
+}
+#[cfg(unix)]
+fn unlock_memory(ptr: *const u8, len: usize) {
+    // SAFETY: same as above, munlock on a range that isn't locked is harmless
+    unsafe {
+        libc::munlock(ptr as *const libc::c_void, len);
+    }
+}
+#[cfg(not(unix))]
+fn lock_memory(_ptr: *const u8, _len: usize) {}
+#[cfg(not(unix))]
+fn unlock_memory(_ptr: *const u8, _len: usize) {}
+
+#[cfg(test)]
+mod tests {
+    use super::{Key, SecretString};
+
+    #[test]
+    fn redacted_debug() {
+        let key = Key::from_bytes(&[0xab; 32]);
+        assert!(!format!("{:?}", key).contains("171"));
+        let secret = SecretString::from("hunter2");
+        assert!(!format!("{:?}", secret).contains("hunter2"));
+    }
+    #[test]
+    fn key_bytes() {
+        let mut key = Key::zeroed();
+        assert_eq!(key.as_bytes(), &[0u8; 32]);
+        key.as_mut_bytes()[0] = 1;
+        assert_eq!(key.clone(), key);
+        assert_ne!(key, Key::zeroed());
+    }
+    #[test]
+    fn into_inner() {
+        let secret = SecretString::from("hunter2");
+        assert_eq!(secret.expose_secret(), "hunter2");
+        assert_eq!(secret.into_inner(), "hunter2");
+    }
+}
@@ -0,0 +1,606 @@
+// estimating how hard a password is to guess, in the style of zxcvbn. the password is split into the patterns
+// an attacker would try first (common passwords, the entry's own name, keyboard patterns, sequences, repeats and dates),
+// anything else is brute forced, and the cheapest way to put it together is what the password is worth.
+
+use std::collections::HashMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The lowest score that isn't considered weak.
+pub const RECOMMENDED_SCORE: u8 = 3;
+
+/// How many guesses per second an attacker is assumed to make, offline against a slow hash (like zxcvbn does).
+pub const GUESSES_PER_SECOND: f64 = 1e4;
+
+// the longest password that's looked at closely, anything after it is always brute forced.
+// some of the matching is quadratic (or worse) in the length, and long passwords are strong anyway
+const MAX_ANALYZED_LENGTH: usize = 64;
+
+// guesses per character that isn't part of a pattern, in log10 (zxcvbn's brute force cardinality of 10)
+const BRUTEFORCE_LOG10: f64 = 1.0;
+
+// a couple hundred of the most common passwords and words in them, roughly from most to least common.
+// the position in the list is how many guesses it takes to find the word
+const COMMON_PASSWORDS: &str = "\
+    password 123456 qwerty letmein welcome monkey dragon football baseball iloveyou admin login \
+    master sunshine princess shadow superman batman trustno1 hello freedom whatever michael \
+    jennifer jordan hunter ranger buster soccer hockey killer george charlie andrew michelle \
+    love secret summer winter spring autumn flower pepper cheese computer internet starwars \
+    pokemon ginger tigger thomas robert daniel matthew jessica ashley amanda nicole harley \
+    cookie chocolate banana orange purple silver golden diamond blessed family friends forever \
+    angel lovely money mustang corvette ferrari yankees cowboys dallas liverpool chelsea arsenal \
+    access changeme default guest root test user pass maggie bailey buddy daisy lucky molly \
+    sophie peanut butterfly rainbow unicorn dolphin tiger eagle falcon phoenix wizard ninja \
+    pirate zombie matrix hacker gamer player warrior knight king queen prince baby sweet honey \
+    sugar candy secure abc passw0rd welcome1 monkey1 jesus god heaven happy smile music guitar \
+    piano dance party beach ocean summer1 apple google facebook twitter youtube microsoft \
+    windows linux android samsung iphone nintendo playstation xbox minecraft fortnite pepsi \
+    coffee pizza chicken hotdog cowboy horse kitten puppy doggy kitty bear panda monster \
+    spiderman ironman thunder lightning storm fire water earth star moon sun sky blue red green \
+    black white yellow pink hello1 test1 admin1 root1 qwertyuiop asdfghjkl zxcvbnm 1qaz2wsx \
+    zaq12wsx qazwsx";
+
+/// How strong a password is.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Strength {
+    /// From 0 (guessable in minutes) to 4 (practically unguessable), like zxcvbn's scores
+    pub score: u8,
+    /// The estimated number of guesses to find the password, in log10
+    pub guesses_log10: f64,
+    /// What makes the password easy to guess, if it's weak because of a pattern
+    pub warning: Option<&'static str>,
+}
+
+impl Strength {
+    /// How long an offline attack against a slow hash would take, e.g. "3 hours".
+    pub fn crack_time(&self) -> String {
+        display_time(10f64.powf(self.guesses_log10) / GUESSES_PER_SECOND)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Pattern {
+    Dictionary,
+    UserInput,
+    Keyboard,
+    Sequence,
+    Repeat,
+    Date,
+}
+
+impl Pattern {
+    fn warning(self) -> &'static str {
+        match self {
+            Pattern::Dictionary => "it's a common password, or made of common words",
+            Pattern::UserInput => "it contains the entry's name, username or email",
+            Pattern::Keyboard => {
+                "keys that are next to each other on the keyboard are easy to guess"
+            }
+            Pattern::Sequence => "sequences like abc or 6543 are easy to guess",
+            Pattern::Repeat => "repeated characters or words like aaa or abcabc are easy to guess",
+            Pattern::Date => "dates and years are easy to guess",
+        }
+    }
+}
+
+// a part of the password that follows a pattern, `start..end` in characters
+#[derive(Clone, Copy, Debug)]
+struct Match {
+    start: usize,
+    end: usize,
+    guesses_log10: f64,
+    pattern: Pattern,
+}
+
+/// Estimates how strong `password` is. `user_inputs` are things an attacker would try first because they're
+/// related to the password, like the entry's name and username.
+pub fn estimate(password: &str, user_inputs: &[&str]) -> Strength {
+    let chars: Vec<char> = password.chars().collect();
+    let analyzed = &chars[..chars.len().min(MAX_ANALYZED_LENGTH)];
+    let rest = (chars.len() - analyzed.len()) as f64 * BRUTEFORCE_LOG10;
+    let (guesses_log10, matches) = cheapest_guesses(analyzed, user_inputs, &mut HashMap::new());
+    let guesses_log10 = guesses_log10 + rest;
+    let score = match guesses_log10 {
+        g if g < 3.0 => 0,
+        g if g < 6.0 => 1,
+        g if g < 8.0 => 2,
+        g if g < 10.0 => 3,
+        _ => 4,
+    };
+    // the longest pattern is what the password is weak because of
+    let warning = matches
+        .iter()
+        .max_by_key(|m| m.end - m.start)
+        .filter(|_| score < 3)
+        .map(|m| m.pattern.warning());
+    Strength {
+        score,
+        guesses_log10,
+        warning,
+    }
+}
+
+// the fewest guesses needed for `chars`, and the patterns that were used for it.
+// guesses[j] is the cheapest way to guess the first j characters: either brute force the jth character,
+// or guess a whole match that ends there.
+// `repeated` remembers what the strings repeat matches are made of are worth, see repeat_matches()
+fn cheapest_guesses(
+    chars: &[char],
+    user_inputs: &[&str],
+    repeated: &mut HashMap<Vec<char>, f64>,
+) -> (f64, Vec<Match>) {
+    let matches = find_matches(chars, user_inputs, repeated);
+    let mut guesses = vec![0.0; chars.len() + 1];
+    let mut used: Vec<Option<Match>> = vec![None; chars.len() + 1];
+    for end in 1..=chars.len() {
+        guesses[end] = guesses[end - 1] + BRUTEFORCE_LOG10;
+        for m in matches.iter().filter(|m| m.end == end) {
+            let total = guesses[m.start] + m.guesses_log10;
+            if total < guesses[end] {
+                guesses[end] = total;
+                used[end] = Some(*m);
+            }
+        }
+    }
+    let mut path = Vec::new();
+    let mut end = chars.len();
+    while end > 0 {
+        match used[end] {
+            Some(m) => {
+                path.push(m);
+                end = m.start;
+            }
+            None => end -= 1,
+        }
+    }
+    (guesses[chars.len()], path)
+}
+
+fn find_matches(
+    chars: &[char],
+    user_inputs: &[&str],
+    repeated: &mut HashMap<Vec<char>, f64>,
+) -> Vec<Match> {
+    let mut matches = dictionary_matches(chars, user_inputs);
+    matches.extend(keyboard_matches(chars));
+    matches.extend(sequence_matches(chars));
+    matches.extend(repeat_matches(chars, user_inputs, repeated));
+    matches.extend(date_matches(chars));
+    matches
+}
+
+// how many more guesses the capitalization of a word takes: none if it's all lowercase,
+// one more bit if only the first (or every) letter is uppercase, otherwise a bit per uppercase letter
+fn uppercase_log10(word: &[char]) -> f64 {
+    let upper = word.iter().filter(|c| c.is_uppercase()).count();
+    let letters = word.iter().filter(|c| c.is_alphabetic()).count();
+    if upper == 0 {
+        0.0
+    } else if upper == letters || (upper == 1 && word[0].is_uppercase()) {
+        2f64.log10()
+    } else {
+        upper.min(letters - upper) as f64 * 2f64.log10()
+    }
+}
+
+// common substitutions like p4ssw0rd, with 1 and ! standing in for i (or l, see below)
+fn unleet(c: char, one: char) -> char {
+    match c {
+        '4' | '@' => 'a',
+        '3' => 'e',
+        '0' => 'o',
+        '1' | '!' => one,
+        '$' | '5' => 's',
+        '7' | '+' => 't',
+        c => c,
+    }
+}
+
+fn dictionary_matches(chars: &[char], user_inputs: &[&str]) -> Vec<Match> {
+    // the inputs themselves, and the words in them, like the parts of an email address
+    let user_inputs: Vec<String> = user_inputs
+        .iter()
+        .map(|input| input.to_lowercase())
+        .flat_map(|input| {
+            let words: Vec<String> = input
+                .split(|c: char| !c.is_alphanumeric())
+                .map(|word| word.to_string())
+                .collect();
+            std::iter::once(input).chain(words)
+        })
+        .filter(|input| input.chars().count() >= 3)
+        .collect();
+    let lower: Vec<char> = chars.iter().flat_map(|c| c.to_lowercase()).collect();
+    // lowercasing can change the length of some (non-ascii) strings, which would break the positions
+    if lower.len() != chars.len() {
+        return Vec::new();
+    }
+    let mut matches = Vec::new();
+    for start in 0..chars.len() {
+        for end in start + 3..=chars.len() {
+            let word = &lower[start..end];
+            let plain: String = word.iter().collect();
+            let reversed: String = word.iter().rev().collect();
+            let leet_i: String = word.iter().map(|c| unleet(*c, 'i')).collect();
+            let leet_l: String = word.iter().map(|c| unleet(*c, 'l')).collect();
+            // each way of writing a word doubles the guesses
+            let candidates = [
+                (plain.clone(), 0.0),
+                (reversed, 2f64.log10()),
+                (leet_i, 2f64.log10()),
+                (leet_l, 2f64.log10()),
+            ];
+            for (candidate, extra) in candidates {
+                // e.g. a word without any substitutions in it
+                if extra > 0.0 && candidate == plain {
+                    continue;
+                }
+                let found = user_inputs
+                    .iter()
+                    .position(|input| *input == candidate)
+                    .map(|rank| (rank, Pattern::UserInput))
+                    .or_else(|| {
+                        COMMON_PASSWORDS
+                            .split_whitespace()
+                            .position(|common| common == candidate)
+                            .map(|rank| (rank, Pattern::Dictionary))
+                    });
+                if let Some((rank, pattern)) = found {
+                    matches.push(Match {
+                        start,
+                        end,
+                        guesses_log10: ((rank + 1) as f64).log10()
+                            + extra
+                            + uppercase_log10(&chars[start..end]),
+                        pattern,
+                    });
+                }
+            }
+        }
+    }
+    matches
+}
+
+// where a key is on a qwerty keyboard, as its row and its position across the row in half keys,
+// since the rows are staggered. shifted characters are on the same key as unshifted ones
+fn key_position(c: char) -> Option<(i32, i32)> {
+    const ROWS: [(&str, &str, i32); 4] = [
+        ("`1234567890-=", "~!@#$%^&*()_+", 0),
+        ("qwertyuiop[]\\", "QWERTYUIOP{}|", 3),
+        ("asdfghjkl;'", "ASDFGHJKL:\"", 4),
+        ("zxcvbnm,./", "ZXCVBNM<>?", 5),
+    ];
+    ROWS.iter()
+        .enumerate()
+        .find_map(|(row, (keys, shifted, offset))| {
+            keys.chars()
+                .position(|key| key == c)
+                .or_else(|| shifted.chars().position(|key| key == c))
+                .map(|column| (row as i32, column as i32 * 2 + offset))
+        })
+}
+
+// which way the second key is from the first, if they're next to each other
+fn key_direction(a: char, b: char) -> Option<(i32, i32)> {
+    let (row_a, x_a) = key_position(a)?;
+    let (row_b, x_b) = key_position(b)?;
+    let direction = (row_b - row_a, x_b - x_a);
+    match direction {
+        (0, -2) | (0, 2) | (-1, -1) | (-1, 1) | (1, -1) | (1, 1) => Some(direction),
+        _ => None,
+    }
+}
+
+// runs of keys that are next to each other, like qwerty or 1qaz.
+// like zxcvbn, the guesses depend on the length and how often the run changes direction
+fn keyboard_matches(chars: &[char]) -> Vec<Match> {
+    // keys on the keyboard, and how many neighbours a key has on average
+    const STARTING_POSITIONS: f64 = 94.0;
+    const AVERAGE_DEGREE: f64 = 4.6;
+    let mut matches = Vec::new();
+    let mut start = 0;
+    while start < chars.len() {
+        let mut end = start + 1;
+        let mut turns = 0;
+        let mut last_direction = None;
+        while end < chars.len() {
+            let Some(direction) = key_direction(chars[end - 1], chars[end]) else {
+                break;
+            };
+            if last_direction != Some(direction) {
+                turns += 1;
+                last_direction = Some(direction);
+            }
+            end += 1;
+        }
+        let length = end - start;
+        if length >= 3 {
+            let mut guesses = 0.0;
+            for i in 2..=length {
+                for j in 1..=turns.min(i - 1) {
+                    guesses +=
+                        binomial(i - 1, j - 1) * STARTING_POSITIONS * AVERAGE_DEGREE.powi(j as i32);
+                }
+            }
+            matches.push(Match {
+                start,
+                end,
+                guesses_log10: guesses.log10(),
+                pattern: Pattern::Keyboard,
+            });
+        }
+        start = end.max(start + 1);
+    }
+    matches
+}
+
+fn binomial(n: usize, k: usize) -> f64 {
+    (0..k).fold(1.0, |result, i| result * (n - i) as f64 / (i + 1) as f64)
+}
+
+// runs of characters that count up or down by one, like abcd or 9876
+fn sequence_matches(chars: &[char]) -> Vec<Match> {
+    let mut matches = Vec::new();
+    let mut start = 0;
+    while start + 1 < chars.len() {
+        let delta = chars[start + 1] as i64 - chars[start] as i64;
+        let mut end = start + 1;
+        while end < chars.len() && chars[end] as i64 - chars[end - 1] as i64 == delta {
+            end += 1;
+        }
+        if delta.abs() == 1 && end - start >= 3 {
+            let first = chars[start];
+            // obvious starting points are tried first
+            let base: f64 = if "aAzZ019".contains(first) {
+                4.0
+            } else if first.is_ascii_digit() {
+                10.0
+            } else {
+                26.0
+            };
+            let direction = if delta < 0 { 2.0 } else { 1.0 };
+            matches.push(Match {
+                start,
+                end,
+                guesses_log10: (base * direction * (end - start) as f64).log10(),
+                pattern: Pattern::Sequence,
+            });
+            start = end - 1;
+        } else {
+            start += 1;
+        }
+    }
+    matches
+}
+
+// a string repeated at least twice, like aaaa or abcabc. it takes as many guesses as the string,
+// times the number of repeats. the same strings come up over and over (every start and period of aaaa…,
+// and again inside each of them), so what they're worth is only worked out once
+fn repeat_matches(
+    chars: &[char],
+    user_inputs: &[&str],
+    repeated: &mut HashMap<Vec<char>, f64>,
+) -> Vec<Match> {
+    let mut matches = Vec::new();
+    for start in 0..chars.len() {
+        for period in 1..=(chars.len() - start) / 2 {
+            let base = &chars[start..start + period];
+            let mut repeats = 1;
+            while chars[start + repeats * period..]
+                .get(..period)
+                .is_some_and(|next| next == base)
+            {
+                repeats += 1;
+            }
+            if repeats >= 2 && repeats * period >= 3 {
+                let base_guesses = match repeated.get(base) {
+                    Some(&guesses) => guesses,
+                    None => {
+                        let (guesses, _) = cheapest_guesses(base, user_inputs, repeated);
+                        repeated.insert(base.to_vec(), guesses);
+                        guesses
+                    }
+                };
+                matches.push(Match {
+                    start,
+                    end: start + repeats * period,
+                    guesses_log10: base_guesses + (repeats as f64).log10(),
+                    pattern: Pattern::Repeat,
+                });
+            }
+        }
+    }
+    matches
+}
+
+fn current_year() -> i64 {
+    let seconds = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|t| t.as_secs())
+        .unwrap_or_default();
+    1970 + seconds as i64 / 31_556_952
+}
+
+// years are guessed outwards from the current one, but at least 20 of them are tried
+fn year_guesses(year: i64) -> f64 {
+    ((year - current_year()).abs().max(20)) as f64
+}
+
+// two digit years are 1950 to 2049
+fn full_year(year: i64, digits: usize) -> i64 {
+    match (digits, year) {
+        (2, 50..) => 1900 + year,
+        (2, _) => 2000 + year,
+        _ => year,
+    }
+}
+
+// whether the numbers are a day, month and year in some order, and which year
+fn as_date(numbers: &[(i64, usize)]) -> Option<i64> {
+    let valid = |day: i64, month: i64| (1..=31).contains(&day) && (1..=12).contains(&month);
+    match numbers {
+        [(a, _), (b, _), (year, digits)]
+            if matches!(digits, 2 | 4) && (valid(*a, *b) || valid(*b, *a)) =>
+        {
+            Some(full_year(*year, *digits))
+        }
+        [(year, 4), (a, _), (b, _)] if valid(*a, *b) || valid(*b, *a) => Some(*year),
+        _ => None,
+    }
+}
+
+// years like 1987, and dates like 31.12.1999, 4/7/85 or 19991231
+fn date_matches(chars: &[char]) -> Vec<Match> {
+    let mut matches = Vec::new();
+    let this_year = current_year();
+    for start in 0..chars.len() {
+        for end in start + 4..=chars.len().min(start + 10) {
+            let text: String = chars[start..end].iter().collect();
+            let date = if text.chars().all(|c| c.is_ascii_digit()) {
+                match text.len() {
+                    4 => text
+                        .parse::<i64>()
+                        .ok()
+                        .filter(|year| (1900..=this_year + 20).contains(year))
+                        .map(|year| (year, false, false)),
+                    // every way of splitting the digits into a day, month and year
+                    6 | 8 => {
+                        let year_digits = text.len() - 4;
+                        let splits = [
+                            [(0, 2), (2, 4), (4, text.len())],
+                            [
+                                (0, year_digits),
+                                (year_digits, year_digits + 2),
+                                (year_digits + 2, text.len()),
+                            ],
+                        ];
+                        splits.iter().find_map(|split| {
+                            let numbers: Vec<(i64, usize)> = split
+                                .iter()
+                                .map(|(from, to)| {
+                                    (text[*from..*to].parse().unwrap_or(0), to - from)
+                                })
+                                .collect();
+                            as_date(&numbers).map(|year| (year, true, false))
+                        })
+                    }
+                    _ => None,
+                }
+            } else {
+                let parts: Vec<&str> = text.split(['/', '-', '.', ' ', '_']).collect();
+                let separators: Vec<char> = text.chars().filter(|c| !c.is_ascii_digit()).collect();
+                let numbers: Option<Vec<(i64, usize)>> = parts
+                    .iter()
+                    .map(|part| {
+                        (!part.is_empty() && part.len() <= 4)
+                            .then(|| part.parse().ok().map(|number| (number, part.len())))
+                            .flatten()
+                    })
+                    .collect();
+                // both separators have to be the same
+                match numbers {
+                    Some(numbers) if separators.len() == 2 && separators[0] == separators[1] => {
+                        as_date(&numbers).map(|year| (year, true, true))
+                    }
+                    _ => None,
+                }
+            };
+            if let Some((year, full_date, separator)) = date {
+                let mut guesses = year_guesses(year);
+                if full_date {
+                    guesses *= 365.0;
+                }
+                if separator {
+                    guesses *= 4.0;
+                }
+                matches.push(Match {
+                    start,
+                    end,
+                    guesses_log10: guesses.log10(),
+                    pattern: Pattern::Date,
+                });
+            }
+        }
+    }
+    matches
+}
+
+/// A rough, readable version of `seconds`, e.g. "3 hours" or "centuries".
+pub fn display_time(seconds: f64) -> String {
+    const MINUTE: f64 = 60.0;
+    const HOUR: f64 = MINUTE * 60.0;
+    const DAY: f64 = HOUR * 24.0;
+    const MONTH: f64 = DAY * 31.0;
+    const YEAR: f64 = MONTH * 12.0;
+    const CENTURY: f64 = YEAR * 100.0;
+    let (amount, unit) = match seconds {
+        s if s < 1.0 => return "less than a second".to_string(),
+        s if s < MINUTE => (s, "second"),
+        s if s < HOUR => (s / MINUTE, "minute"),
+        s if s < DAY => (s / HOUR, "hour"),
+        s if s < MONTH => (s / DAY, "day"),
+        s if s < YEAR => (s / MONTH, "month"),
+        s if s < CENTURY => (s / YEAR, "year"),
+        _ => return "centuries".to_string(),
+    };
+    let amount = amount.round() as u64;
+    let plural = if amount == 1 { "" } else { "s" };
+    format!("{} {}{}", amount, unit, plural)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{display_time, estimate};
+
+    #[test]
+    fn common_passwords() {
+        for password in ["password", "P4ssw0rd", "drowssap", "qwerty", "iloveyou"] {
+            let strength = estimate(password, &[]);
+            assert_eq!(strength.score, 0, "{}", password);
+            assert!(strength.warning.is_some());
+        }
+    }
+    #[test]
+    fn patterns() {
+        assert!(estimate("qwertyuiop", &[]).score <= 1);
+        assert!(estimate("zxcvbnm,./", &[]).score <= 1);
+        assert!(estimate("abcdefghij", &[]).score <= 1);
+        assert!(estimate("98765432", &[]).score <= 1);
+        assert!(estimate("aaaaaaaaaaaa", &[]).score <= 1);
+        assert!(estimate("xkcdxkcdxkcd", &[]).score <= 2);
+        assert!(estimate("31.12.1999", &[]).score <= 2);
+        assert!(estimate("19870412", &[]).score <= 2);
+    }
+    #[test]
+    fn long_repeats() {
+        // every start and period of these used to be scored again from scratch
+        let start = std::time::Instant::now();
+        assert_eq!(estimate(&"a".repeat(64), &[]).score, 0);
+        assert!(estimate(&"abc".repeat(21), &[]).score <= 1);
+        assert!(start.elapsed() < std::time::Duration::from_secs(1));
+    }
+    #[test]
+    fn user_inputs() {
+        let strength = estimate("Github2024", &["github", "me@example.com"]);
+        assert!(strength.score <= 1);
+        assert_eq!(
+            strength.warning,
+            Some("it contains the entry's name, username or email")
+        );
+        // without the entry's name, it's only somewhat guessable
+        assert!(estimate("Githab2024", &["github"]).score > strength.score);
+    }
+    #[test]
+    fn random_passwords() {
+        let strength = estimate("f|n6XVU<B.@1-6$\\", &[]);
+        assert_eq!(strength.score, 4);
+        assert_eq!(strength.warning, None);
+        assert_eq!(strength.crack_time(), "centuries");
+    }
+    #[test]
+    fn times() {
+        assert_eq!(display_time(0.5), "less than a second");
+        assert_eq!(display_time(1.0), "1 second");
+        assert_eq!(display_time(3.0 * 3600.0), "3 hours");
+        assert_eq!(display_time(1e12), "centuries");
+    }
+}
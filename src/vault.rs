@@ -0,0 +1,474 @@
+// a higher level api over ops, for anything that embeds pwd-rs
+// a Vault is just an open database, the only way to read or write entries is to unlock() it first,
+// which checks the master password and unwraps the vault's data key once for the whole session.
+
+use std::time::{Duration, SystemTime};
+
+use diesel::prelude::*;
+use diesel::sqlite::SqliteConnection;
+
+use crate::kdf::{Kdf, KdfHeader};
+use crate::models::Password;
+use crate::ops;
+use crate::secret::{self, Key};
+use crate::{Error, Result};
+use zeroize::{Zeroize, ZeroizeOnDrop};
+
+/// A decrypted password entry. Its fields are wiped when it's dropped.
+/// It doesn't implement `PartialEq`, which wouldn't compare the password in constant time.
+#[derive(Clone, Default, Zeroize, ZeroizeOnDrop, serde::Serialize)]
+pub struct Entry {
+    pub name: String,
+    pub username: Option<String>,
+    pub email: Option<String>,
+    pub pass: Option<String>,
+    pub notes: Option<String>,
+}
+
+impl From<Password> for Entry {
+    fn from(mut record: Password) -> Self {
+        Entry {
+            name: std::mem::take(&mut record.name),
+            username: record.username.take(),
+            email: record.email.take(),
+            pass: record.pass.take(),
+            notes: record.notes.take(),
+        }
+    }
+}
+
+/// One of an entry's fields.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum Field {
+    Name,
+    Username,
+    Email,
+    Pass,
+    Notes,
+}
+
+impl std::fmt::Display for Field {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let name = match self {
+            Field::Name => "name",
+            Field::Username => "username",
+            Field::Email => "email",
+            Field::Pass => "pass",
+            Field::Notes => "notes",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl Field {
+    /// Whether the field holds a secret, which `get --mask` hides. Notes count, since they often hold
+    /// things like recovery codes.
+    pub fn is_secret(self) -> bool {
+        matches!(self, Field::Pass | Field::Notes)
+    }
+}
+
+impl Entry {
+    /// Returns one of the entry's fields, or `None` if it isn't set.
+    pub fn field(&self, field: Field) -> Option<&str> {
+        match field {
+            Field::Name => Some(&self.name),
+            Field::Username => self.username.as_deref(),
+            Field::Email => self.email.as_deref(),
+            Field::Pass => self.pass.as_deref(),
+            Field::Notes => self.notes.as_deref(),
+        }
+    }
+}
+
+/// Changes to make to an existing entry. Fields that are `None` are left as they are.
+/// Like `Entry`, its fields are wiped when it's dropped.
+#[derive(Clone, Default, Zeroize, ZeroizeOnDrop)]
+pub struct EntryUpdate {
+    pub new_name: Option<String>,
+    pub username: Option<String>,
+    pub email: Option<String>,
+    pub pass: Option<String>,
+    pub notes: Option<String>,
+}
+
+// like the types in secret.rs, entries never show their password or notes in Debug output,
+// only whether they're set
+fn redacted(value: &Option<String>) -> Option<&'static str> {
+    value.as_ref().map(|_| "[redacted]")
+}
+
+impl std::fmt::Debug for Entry {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("Entry")
+            .field("name", &self.name)
+            .field("username", &self.username)
+            .field("email", &self.email)
+            .field("pass", &redacted(&self.pass))
+            .field("notes", &redacted(&self.notes))
+            .finish()
+    }
+}
+
+impl std::fmt::Debug for EntryUpdate {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("EntryUpdate")
+            .field("new_name", &self.new_name)
+            .field("username", &self.username)
+            .field("email", &self.email)
+            .field("pass", &redacted(&self.pass))
+            .field("notes", &redacted(&self.notes))
+            .finish()
+    }
+}
+
+/// What can be told about a vault without unlocking it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Status {
+    /// Whether the vault has a master password yet
+    pub initialized: bool,
+    /// The vault's KDF, if it has a KDF header
+    pub kdf: Option<Kdf>,
+    /// Failed unlocks since the last successful one
+    pub failed_unlocks: u32,
+    /// When the last failed unlock happened, even if there's been a successful one since
+    pub last_failed_unlock: Option<SystemTime>,
+    /// How long the next unlock will wait before checking the master password
+    pub unlock_delay: Duration,
+}
+
+/// A vault that's open, but locked. Call `unlock()` to get at its entries.
+pub struct Vault {
+    connection: SqliteConnection,
+    path: String,
+}
+
+impl Vault {
+    /// Opens the vault database at `path`, creating it if it doesn't exist and running any pending migrations.
+    /// Fails with `Error::Config` if the vault was written by a newer version of pwd-rs.
+    pub fn open(path: &str) -> Result<Vault> {
+        let mut connection = SqliteConnection::establish(path)?;
+        ops::migrate(&mut connection)?;
+        Ok(Vault {
+            connection,
+            path: path.to_string(),
+        })
+    }
+    /// The path the vault was opened with.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+    /// Opens the vault database at `DATABASE_URL`, read from the environment or `.env`.
+    pub fn open_default() -> Result<Vault> {
+        Vault::open(&ops::database_url()?)
+    }
+    /// Returns true if the vault has a master password yet.
+    pub fn is_initialized(&mut self) -> Result<bool> {
+        ops::is_initialized(&mut self.connection)
+    }
+    /// Returns the vault's status, including recent failed unlocks. This doesn't need the master password.
+    pub fn status(&mut self) -> Result<Status> {
+        let (failed_unlocks, last_failed_unlock) = ops::get_failed_unlocks(&mut self.connection)?;
+        Ok(Status {
+            initialized: self.is_initialized()?,
+            kdf: ops::get_kdf_header(&mut self.connection)?.map(|header| header.kdf),
+            failed_unlocks,
+            last_failed_unlock,
+            unlock_delay: ops::remaining_unlock_delay(&mut self.connection)?,
+        })
+    }
+    /// Sets up a new vault protected by `master_password`, deriving its key with `kdf`.
+    /// Fails with `Error::AlreadyExists` if the vault already has a master password.
+    pub fn initialize(&mut self, master_password: &str, kdf: Kdf) -> Result<()> {
+        ops::initialize_vault(&mut self.connection, master_password.as_bytes(), kdf)
+    }
+    /// Checks `master_password` and unlocks the vault.
+    /// After repeated failures this waits before checking the password, see `ops::unlock_delay()`.
+    /// Records written by older versions of pwd-rs are re-encrypted in the current format along the way,
+    /// see `UnlockedVault::upgraded_records()`.
+    /// Core dumps are disabled for the rest of the process from here on, see `secret::disable_core_dumps()`.
+    pub fn unlock(mut self, master_password: &str) -> Result<UnlockedVault> {
+        secret::disable_core_dumps();
+        if !self.is_initialized()? {
+            return Err(Error::NotFound("master password".to_string()));
+        }
+        let Some(key) =
+            ops::authenticate_and_unlock(&mut self.connection, master_password.as_bytes())?
+        else {
+            return Err(Error::Authentication);
+        };
+        let upgrade = ops::upgrade_records(&mut self.connection, master_password)?;
+        Ok(UnlockedVault {
+            connection: self.connection,
+            path: self.path,
+            key,
+            upgraded_records: upgrade.records,
+            renamed_records: upgrade.renamed,
+        })
+    }
+    /// Unlocks the vault with its data key directly, e.g. one handed out by the agent (see `agent::request_key()`),
+    /// without the master password. The key is checked against the one stored in the vault,
+    /// and this fails with `Error::Authentication` if it doesn't match (say the vault was re-initialized since).
+    /// Vaults that haven't been unlocked with the master password since key checks were added always fail.
+    pub fn unlock_with_key(mut self, key: Key) -> Result<UnlockedVault> {
+        secret::disable_core_dumps();
+        if !ops::check_vault_key(&mut self.connection, &key)? {
+            return Err(Error::Authentication);
+        }
+        Ok(UnlockedVault {
+            connection: self.connection,
+            path: self.path,
+            key,
+            upgraded_records: 0,
+            renamed_records: Vec::new(),
+        })
+    }
+}
+
+/// A vault whose master password has been checked. Every entry is encrypted and decrypted
+/// with the data key unwrapped by `Vault::unlock()`, so no key derivation happens after that.
+/// The data key is kept in locked memory and wiped when the vault is locked or dropped.
+pub struct UnlockedVault {
+    connection: SqliteConnection,
+    path: String,
+    key: Key,
+    upgraded_records: usize,
+    renamed_records: Vec<(String, String)>,
+}
+
+impl UnlockedVault {
+    /// The number of records that were re-encrypted in the current format when the vault was unlocked.
+    pub fn upgraded_records(&self) -> usize {
+        self.upgraded_records
+    }
+    /// Entries that were renamed when their records were upgraded, as (old name, new name).
+    /// Older versions compared names exactly, so a vault could hold names that now count as the same,
+    /// like "github" and "GitHub". All but the first of those get a " (2)", " (3)", ... suffix.
+    pub fn renamed_records(&self) -> &[(String, String)] {
+        &self.renamed_records
+    }
+    /// The path the vault was opened with.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+    // the agent needs the key itself, to hand it out
+    pub(crate) fn key(&self) -> &Key {
+        &self.key
+    }
+    /// Adds a new entry. Fails with `Error::AlreadyExists` if there's already an entry with the same name.
+    pub fn add(&mut self, entry: Entry) -> Result<()> {
+        ops::encrypt_and_insert(
+            &mut self.connection,
+            &self.key,
+            &entry.name,
+            entry.username.as_deref(),
+            entry.email.as_deref(),
+            entry.pass.as_deref(),
+            entry.notes.as_deref(),
+        )?;
+        Ok(())
+    }
+    /// Looks an entry up by name. Returns `None` if there isn't one.
+    pub fn get(&mut self, name: &str) -> Result<Option<Entry>> {
+        Ok(ops::read_and_decrypt(&mut self.connection, &self.key, name)?.map(Entry::from))
+    }
+    /// Updates an existing entry. Fails with `Error::NotFound` if there isn't one named `name`.
+    pub fn update(&mut self, name: &str, changes: EntryUpdate) -> Result<()> {
+        let updated = ops::encrypt_and_update(
+            &mut self.connection,
+            &self.key,
+            name,
+            changes.new_name.as_deref(),
+            changes.username.as_deref(),
+            changes.email.as_deref(),
+            changes.pass.as_deref(),
+            changes.notes.as_deref(),
+        )?;
+        if updated == 0 {
+            return Err(Error::NotFound(format!("password \"{}\"", name)));
+        }
+        Ok(())
+    }
+    /// Deletes an entry. Fails with `Error::NotFound` if there isn't one named `name`.
+    pub fn delete(&mut self, name: &str) -> Result<()> {
+        if ops::delete_record(&mut self.connection, &self.key, name)? == 0 {
+            return Err(Error::NotFound(format!("password \"{}\"", name)));
+        }
+        Ok(())
+    }
+    /// Returns the names of every entry.
+    pub fn list(&mut self) -> Result<Vec<String>> {
+        ops::get_all_names(&mut self.connection, &self.key)
+    }
+    /// Writes a copy of the whole (still encrypted) database to `destination`.
+    pub fn backup(&mut self, destination: &str) -> Result<()> {
+        ops::backup_database(&mut self.connection, destination)?;
+        Ok(())
+    }
+    /// Returns the vault's KDF header.
+    pub fn kdf_header(&mut self) -> Result<Option<KdfHeader>> {
+        ops::get_kdf_header(&mut self.connection)
+    }
+    /// Switches the vault to a new KDF. The master password is needed to re-wrap the data key.
+    pub fn change_kdf(&mut self, master_password: &str, kdf: Kdf) -> Result<()> {
+        ops::change_kdf(&mut self.connection, master_password, kdf)
+    }
+    /// Changes the master password. Fails with `Error::Authentication` if `current` is wrong.
+    pub fn change_master_password(&mut self, current: &str, new: &str) -> Result<()> {
+        if !ops::change_master_password(&mut self.connection, current, new)? {
+            return Err(Error::Authentication);
+        }
+        Ok(())
+    }
+    /// Locks the vault again, forgetting the data key.
+    pub fn lock(self) -> Vault {
+        // dropping the key wipes it
+        let UnlockedVault {
+            connection, path, ..
+        } = self;
+        Vault { connection, path }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Entry, EntryUpdate, Vault};
+    use crate::kdf::Kdf;
+    use crate::Error;
+
+    fn create_test_vault() -> Vault {
+        let mut vault = Vault::open(":memory:").unwrap();
+        vault
+            .initialize("mymasterpassword", Kdf::DEFAULT_ARGON2ID)
+            .unwrap();
+        vault
+    }
+
+    #[test]
+    fn unlock() {
+        let vault = create_test_vault();
+        assert!(matches!(
+            vault.unlock("randomguess"),
+            Err(Error::Authentication)
+        ));
+        let vault = create_test_vault();
+        assert!(vault.unlock("mymasterpassword").is_ok());
+    }
+    #[test]
+    fn redacted_debug() {
+        let mut entry = Entry::default();
+        entry.name = "github".to_string();
+        entry.pass = Some("hunter2".to_string());
+        entry.notes = Some("recovery codes".to_string());
+        let debug = format!("{:?}", entry);
+        assert!(debug.contains("github"));
+        assert!(!debug.contains("hunter2") && !debug.contains("recovery codes"));
+        let mut update = EntryUpdate::default();
+        update.pass = Some("hunter2".to_string());
+        assert!(!format!("{:?}", update).contains("hunter2"));
+    }
+    #[test]
+    fn unlock_with_key() {
+        let path = std::env::temp_dir().join(format!("pwd-rs-key-test-{}", std::process::id()));
+        let path = path.to_str().unwrap();
+        let mut vault = Vault::open(path).unwrap();
+        vault
+            .initialize("mymasterpassword", Kdf::DEFAULT_ARGON2ID)
+            .unwrap();
+        let key = vault.unlock("mymasterpassword").unwrap().key().clone();
+        let unlocked = Vault::open(path).unwrap().unlock_with_key(key.clone());
+        std::fs::remove_file(path).unwrap();
+        assert!(unlocked.is_ok());
+        // a vault initialized again has a different data key
+        assert!(matches!(
+            create_test_vault().unlock_with_key(key),
+            Err(Error::Authentication)
+        ));
+    }
+    #[test]
+    fn status() {
+        let path = std::env::temp_dir().join(format!("pwd-rs-status-test-{}", std::process::id()));
+        let path = path.to_str().unwrap();
+        let mut vault = Vault::open(path).unwrap();
+        assert!(!vault.status().unwrap().initialized);
+        vault
+            .initialize("mymasterpassword", Kdf::DEFAULT_ARGON2ID)
+            .unwrap();
+        let status = vault.status().unwrap();
+        assert!(status.initialized);
+        assert_eq!(status.kdf, Some(Kdf::DEFAULT_ARGON2ID));
+        assert_eq!(status.failed_unlocks, 0);
+
+        // failures are stored in the vault, so they're still there after opening it again
+        assert!(matches!(
+            vault.unlock("randomguess"),
+            Err(Error::Authentication)
+        ));
+        let status = Vault::open(path).unwrap().status().unwrap();
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(status.failed_unlocks, 1);
+        assert!(status.last_failed_unlock.is_some());
+    }
+    #[test]
+    fn initialize_twice() {
+        let mut vault = create_test_vault();
+        assert!(matches!(
+            vault.initialize("mymasterpassword", Kdf::DEFAULT_ARGON2ID),
+            Err(Error::AlreadyExists(_))
+        ));
+    }
+    #[test]
+    fn entries() {
+        let mut vault = create_test_vault().unlock("mymasterpassword").unwrap();
+        // entries wipe themselves on drop, so struct update syntax (..Default::default()) can't be used
+        let entry = Entry {
+            name: "github".to_string(),
+            username: Some("tester".to_string()),
+            email: None,
+            pass: Some("hunter2".to_string()),
+            notes: None,
+        };
+        vault.add(entry.clone()).unwrap();
+        // entries don't implement PartialEq, it wouldn't compare passwords in constant time
+        let found = vault.get("github").unwrap().unwrap();
+        assert_eq!(found.name, entry.name);
+        assert_eq!(found.username, entry.username);
+        assert_eq!(found.email, entry.email);
+        assert_eq!(found.pass, entry.pass);
+        assert_eq!(found.notes, entry.notes);
+        let mut duplicate = Entry::default();
+        duplicate.name = "github".to_string();
+        assert!(matches!(vault.add(duplicate), Err(Error::AlreadyExists(_))));
+
+        vault
+            .update(
+                "github",
+                EntryUpdate {
+                    new_name: Some("gitlab".to_string()),
+                    username: None,
+                    email: None,
+                    pass: Some("letmein".to_string()),
+                    notes: None,
+                },
+            )
+            .unwrap();
+        assert!(vault.get("github").unwrap().is_none());
+        let updated = vault.get("gitlab").unwrap().unwrap();
+        assert_eq!(updated.username.as_deref(), Some("tester"));
+        assert_eq!(updated.pass.as_deref(), Some("letmein"));
+        assert_eq!(vault.list().unwrap(), vec!["gitlab"]);
+
+        vault.delete("gitlab").unwrap();
+        assert!(matches!(vault.delete("gitlab"), Err(Error::NotFound(_))));
+        assert!(vault.list().unwrap().is_empty());
+    }
+    #[test]
+    fn lock_and_unlock_again() {
+        let mut vault = create_test_vault().unlock("mymasterpassword").unwrap();
+        let mut entry = Entry::default();
+        entry.name = "github".to_string();
+        vault.add(entry).unwrap();
+        let mut vault = vault.lock().unlock("mymasterpassword").unwrap();
+        assert_eq!(vault.list().unwrap(), vec!["github"]);
+    }
+}